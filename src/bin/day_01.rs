@@ -1,42 +1,595 @@
-use std::path::PathBuf;
+use std::{collections::VecDeque, io, path::PathBuf, str::FromStr};
 use advent_of_code_2021::{from_split_lines, iter_lines};
-use structopt::StructOpt;
+use structopt::{clap, StructOpt};
 
 #[derive(StructOpt)]
 struct Opts {
     part_no: usize,
 
+    /// Depths file, or - to read stdin; may be CSV with a header naming each channel,
+    /// and a time or timestamp column of numeric seconds
     #[structopt(parse(from_os_str), default_value = "data/day_01.txt")]
     filename: PathBuf,
+
+    /// Depths in each sliding window, overriding the part's preset; at least 1
+    #[structopt(long, parse(try_from_str = parse_positive))]
+    window: Option<usize>,
+
+    /// How each window is reduced to one value: sum, mean or max
+    #[structopt(long)]
+    aggregate: Option<Aggregate>,
+
+    /// Which changes between windows to count: increase, decrease or at-least:DELTA
+    #[structopt(long)]
+    compare: Option<Comparison>,
+
+    /// Process depths as they arrive, holding only one window in memory
+    #[structopt(long)]
+    stream: bool,
+
+    /// Describe the runs, plateaus, jumps and outliers in the depths instead of counting changes
+    #[structopt(long)]
+    report: bool,
+
+    /// Depths in the rolling median that outliers are measured against
+    #[structopt(long, default_value = "5")]
+    median_window: usize,
+
+    /// How far a depth must be from the rolling median to be an outlier
+    #[structopt(long, default_value = "10")]
+    outlier: f64,
+
+    /// Print a sparkline and chart of the depths, and of the windowed values if the window is wider than 1
+    #[structopt(long)]
+    chart: bool,
+
+    /// Columns in the terminal chart, at least 1; longer series are averaged down to fit
+    #[structopt(long, default_value = "80", parse(try_from_str = parse_positive))]
+    width: usize,
+
+    /// Rows in the terminal chart, at least 1
+    #[structopt(long, default_value = "10", parse(try_from_str = parse_positive))]
+    height: usize,
+
+    /// Write line charts of the depths, and of the windowed values if the window is wider than 1, to this file as SVG
+    #[structopt(long, parse(from_os_str))]
+    svg: Option<PathBuf>,
+
+    /// Seconds of readings in each sliding window, greater than 0, in place of --window;
+    /// needs a time column of numeric seconds (not dates)
+    #[structopt(long, parse(try_from_str = parse_seconds))]
+    time_window: Option<f64>,
 }
 
 fn parse_input(lines: impl Iterator<Item = String>) -> impl Iterator<Item = u32> {
     from_split_lines(lines, |args: [String; 1]| args[0].parse::<u32>().unwrap())
 }
 
-fn part_1(lines: impl Iterator<Item = String>) -> usize {
+// a count that must be at least 1
+fn parse_positive(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err("Must be at least 1".to_string()),
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("Invalid count '{}'", s)),
+    }
+}
+
+// a span of time that must be more than 0 seconds
+fn parse_seconds(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(t) if t > 0.0 && t.is_finite() => Ok(t),
+        Ok(_) => Err("Must be more than 0 seconds".to_string()),
+        Err(_) => Err(format!("Invalid seconds '{}'", s)),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Aggregate {
+    Sum,
+    Mean,
+    Max,
+}
+
+impl FromStr for Aggregate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sum" => Ok(Aggregate::Sum),
+            "mean" => Ok(Aggregate::Mean),
+            "max" => Ok(Aggregate::Max),
+            _ => Err(format!("Unknown aggregate '{}'", s)),
+        }
+    }
+}
+
+impl Aggregate {
+    fn apply(self, vals: &[u32]) -> f64 {
+        let sum: f64 = vals.iter().map(|v| *v as f64).sum();
+        match self {
+            Aggregate::Sum => sum,
+            Aggregate::Mean => sum / vals.len() as f64,
+            Aggregate::Max => vals.iter().copied().max().unwrap_or(0) as f64,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison {
+    Increase,
+    Decrease,
+    // rises by at least this much
+    AtLeast(f64),
+}
+
+impl FromStr for Comparison {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "increase" => Ok(Comparison::Increase),
+            None if s == "decrease" => Ok(Comparison::Decrease),
+            Some(("at-least", d)) => d.parse().map(Comparison::AtLeast).map_err(|_| format!("Invalid delta '{}'", d)),
+            _ => Err(format!("Unknown comparison '{}'", s)),
+        }
+    }
+}
+
+impl Comparison {
+    fn matches(self, prev: f64, next: f64) -> bool {
+        match self {
+            Comparison::Increase => next > prev,
+            Comparison::Decrease => next < prev,
+            Comparison::AtLeast(delta) => next - prev >= delta,
+        }
+    }
+}
+
+// count the changes between successive windows that match the comparison
+#[derive(Debug, PartialEq)]
+struct Analysis {
+    window: usize,
+    aggregate: Aggregate,
+    compare: Comparison,
+}
+
+impl Analysis {
+    // part 1 compares single depths, part 2 sums of three
+    fn preset(part_no: usize) -> Self {
+        let window = match part_no {
+            1 => 1,
+            2 => 3,
+            _ => panic!("Invalid part"),
+        };
+        Analysis { window, aggregate: Aggregate::Sum, compare: Comparison::Increase }
+    }
+
+    fn windowed(&self, depths: &[u32]) -> Vec<f64> {
+        depths.windows(self.window).map(|vals| self.aggregate.apply(vals)).collect()
+    }
+
+    fn count(&self, depths: &[u32]) -> usize {
+        self.count_matches(&self.windowed(depths))
+    }
+
+    fn count_matches(&self, windowed: &[f64]) -> usize {
+        windowed
+            .windows(2)
+            .filter(|vals| self.compare.matches(vals[0], vals[1]))
+            .count()
+    }
+}
+
+fn count_changes(lines: impl Iterator<Item = String>, analysis: &Analysis) -> usize {
     let depths: Vec<u32> = parse_input(lines).collect();
-    let increases = depths.windows(2).filter(|vals| vals[1] > vals[0]).count();
-    increases
+    analysis.count(&depths)
+}
+
+// depths from one or more sonar channels, with the time of each row if known
+#[derive(Debug, PartialEq)]
+struct Readings {
+    times: Option<Vec<f64>>,
+    // each channel's name and its depth on every row, None where the cell is empty
+    channels: Vec<(String, Vec<Option<u32>>)>,
+}
+
+fn is_time_column(name: &str) -> bool {
+    matches!(name.to_lowercase().as_str(), "time" | "timestamp")
+}
+
+// either a bare depth per line, or CSV with a header naming the channels and
+// optionally a time column
+fn parse_readings(lines: impl Iterator<Item = String>) -> Readings {
+    let mut lines = lines.filter(|l| !l.trim().is_empty()).peekable();
+    let header = match lines.peek() {
+        Some(l) if l.trim().parse::<u32>().is_err() => lines.next().unwrap(),
+        _ => {
+            let depths = parse_input(lines).map(Some).collect();
+            return Readings { times: None, channels: vec![("depth".to_string(), depths)] };
+        }
+    };
+    let columns: Vec<_> = header.split(',').map(|c| c.trim().to_string()).collect();
+    let time_column = columns.iter().position(|c| is_time_column(c));
+    let mut times = time_column.map(|_| vec![]);
+    let mut channels: Vec<_> = columns.iter()
+        .filter(|c| !is_time_column(c))
+        .map(|c| (c.clone(), vec![]))
+        .collect();
+    for l in lines {
+        let mut channel = channels.iter_mut();
+        for (i, cell) in l.split(',').map(str::trim).enumerate() {
+            if Some(i) == time_column {
+                times.as_mut().unwrap().push(cell.parse().expect("Invalid time"));
+            } else {
+                let (_, depths) = channel.next().expect("More cells than columns");
+                depths.push(if cell.is_empty() { None } else { Some(cell.parse().expect("Invalid depth")) });
+            }
+        }
+        // short rows are missing their last channels
+        for (_, depths) in channel {
+            depths.push(None);
+        }
+    }
+    Readings { times, channels }
+}
+
+// aggregate the readings in the width seconds up to and including each reading,
+// (t - width, t], so the windows slide as a count of depths does. The recording is
+// taken to start one sampling interval before the first time, and windows reaching
+// back before then are skipped as partial.
+fn time_windowed(times: &[f64], depths: &[Option<u32>], width: f64, aggregate: Aggregate) -> Vec<f64> {
+    let start = match times {
+        [t0, t1, ..] => t0 - (t1 - t0),
+        _ => return vec![],
+    };
+    let readings: Vec<(f64, u32)> = times.iter().zip(depths)
+        .filter_map(|(t, d)| d.map(|d| (*t, d)))
+        .collect();
+    let mut windowed = vec![];
+    let mut first = 0;
+    for (i, (t, _)) in readings.iter().enumerate() {
+        // drop the readings that have slid out of the window
+        while readings[first].0 <= t - width {
+            first += 1;
+        }
+        if t - width >= start {
+            let vals: Vec<u32> = readings[first..=i].iter().map(|(_, d)| *d).collect();
+            windowed.push(aggregate.apply(&vals));
+        }
+    }
+    windowed
+}
+
+// the changes counted on each channel, as "name: count"
+fn channel_counts(readings: &Readings, analysis: &Analysis, time_window: Option<f64>) -> Vec<String> {
+    readings.channels.iter()
+        .map(|(name, depths)| {
+            let count = match time_window {
+                Some(width) => {
+                    let times = readings.times.as_ref().expect("Time windows need a time column");
+                    analysis.count_matches(&time_windowed(times, depths, width, analysis.aggregate))
+                }
+                None => analysis.count(&depths.iter().flatten().copied().collect::<Vec<_>>()),
+            };
+            format!("{}: {}", name, count)
+        })
+        .collect()
+}
+
+// as count_changes, but only ever holding the current window
+fn count_streaming(depths: impl Iterator<Item = u32>, analysis: &Analysis) -> usize {
+    let mut ring = VecDeque::with_capacity(analysis.window + 1);
+    let mut prev = None;
+    let mut count = 0;
+    for d in depths {
+        ring.push_back(d);
+        if ring.len() > analysis.window {
+            ring.pop_front();
+        }
+        if ring.len() < analysis.window {
+            continue;
+        }
+        let next = analysis.aggregate.apply(ring.make_contiguous());
+        if let Some(prev) = prev {
+            if analysis.compare.matches(prev, next) {
+                count += 1;
+            }
+        }
+        prev = Some(next);
+    }
+    count
+}
+
+// first longest stretch where every step satisfies cmp, as (start, end) indexes
+fn longest_run(depths: &[u32], cmp: impl Fn(u32, u32) -> bool) -> Option<(usize, usize)> {
+    let mut longest: Option<(usize, usize)> = None;
+    let mut start = 0;
+    for i in 1..depths.len() {
+        if !cmp(depths[i - 1], depths[i]) {
+            start = i;
+        } else if longest.is_none_or(|(s, e)| i - start > e - s) {
+            longest = Some((start, i));
+        }
+    }
+    longest
+}
+
+// stretches of two or more equal depths, as (start, end) indexes
+fn plateaus(depths: &[u32]) -> Vec<(usize, usize)> {
+    let mut plateaus = vec![];
+    let mut start = 0;
+    for i in 1..=depths.len() {
+        if i == depths.len() || depths[i] != depths[start] {
+            if i - start > 1 {
+                plateaus.push((start, i - 1));
+            }
+            start = i;
+        }
+    }
+    plateaus
+}
+
+// the n biggest changes from one depth to the next, as (index before, change)
+fn largest_jumps(depths: &[u32], n: usize) -> Vec<(usize, i64)> {
+    let mut jumps: Vec<_> = depths.windows(2)
+        .enumerate()
+        .map(|(i, vals)| (i, vals[1] as i64 - vals[0] as i64))
+        .collect();
+    jumps.sort_by_key(|(i, d)| (std::cmp::Reverse(d.abs()), *i));
+    jumps.truncate(n);
+    jumps
+}
+
+// median of the window centred on each depth, shrinking at the ends
+fn rolling_median(depths: &[u32], window: usize) -> Vec<f64> {
+    let half = window / 2;
+    (0..depths.len())
+        .map(|i| {
+            let mut vals = depths[i.saturating_sub(half)..(i + half + 1).min(depths.len())].to_vec();
+            vals.sort_unstable();
+            let mid = vals.len() / 2;
+            if vals.len().is_multiple_of(2) {
+                (vals[mid - 1] as f64 + vals[mid] as f64) / 2.0
+            } else {
+                vals[mid] as f64
+            }
+        })
+        .collect()
+}
+
+// depths further than threshold from their rolling median, as (index, depth, median)
+fn outliers(depths: &[u32], window: usize, threshold: f64) -> Vec<(usize, u32, f64)> {
+    depths.iter()
+        .zip(rolling_median(depths, window))
+        .enumerate()
+        .filter(|(_, (d, m))| (**d as f64 - m).abs() > threshold)
+        .map(|(i, (d, m))| (i, *d, m))
+        .collect()
+}
+
+fn report(depths: &[u32], median_window: usize, threshold: f64) -> Vec<String> {
+    let mut lines = vec![];
+    let runs = [("increasing", longest_run(depths, |a, b| b > a)), ("decreasing", longest_run(depths, |a, b| b < a))];
+    for (name, run) in runs {
+        match run {
+            Some((s, e)) => lines.push(format!("longest {} run: {} steps, indexes {} to {} ({} -> {})",
+                name, e - s, s, e, depths[s], depths[e])),
+            None => lines.push(format!("longest {} run: none", name)),
+        }
+    }
+    for (s, e) in plateaus(depths) {
+        lines.push(format!("plateau: indexes {} to {} at {}", s, e, depths[s]));
+    }
+    for (i, d) in largest_jumps(depths, 3) {
+        lines.push(format!("jump: {:+} from index {} to {} ({} -> {})", d, i, i + 1, depths[i], depths[i + 1]));
+    }
+    for (i, d, m) in outliers(depths, median_window, threshold) {
+        lines.push(format!("outlier: index {} at {}, rolling median {}", i, d, m));
+    }
+    lines
+}
+
+// average runs of values so there are at most width of them
+fn downsample(series: &[f64], width: usize) -> Vec<f64> {
+    if series.len() <= width {
+        return series.to_vec();
+    }
+    (0..width)
+        .map(|i| {
+            let bucket = &series[i * series.len() / width..(i + 1) * series.len() / width];
+            bucket.iter().sum::<f64>() / bucket.len() as f64
+        })
+        .collect()
+}
+
+fn bounds(series: &[f64]) -> (f64, f64) {
+    series.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), v| (lo.min(*v), hi.max(*v)))
+}
+
+// how far up the range v is, from 0 to 1
+fn scaled(v: f64, (lo, hi): (f64, f64)) -> f64 {
+    if hi > lo { (v - lo) / (hi - lo) } else { 0.5 }
+}
+
+fn sparkline(series: &[f64], width: usize) -> String {
+    const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let series = downsample(series, width);
+    let range = bounds(&series);
+    series.iter().map(|v| BARS[(scaled(*v, range) * 7.0).round() as usize]).collect()
+}
+
+// each column is drawn as /, \ or _ by whether it rose, fell or held from the last,
+// with the top and bottom of the range labelled
+fn ascii_chart(series: &[f64], width: usize, height: usize) -> Vec<String> {
+    let series = downsample(series, width);
+    let range = bounds(&series);
+    let mut rows = vec![vec![' '; series.len()]; height];
+    for (i, v) in series.iter().enumerate() {
+        let r = ((1.0 - scaled(*v, range)) * (height - 1) as f64).round() as usize;
+        rows[r][i] = match i.checked_sub(1).map(|j| series[j]) {
+            Some(prev) if *v > prev => '/',
+            Some(prev) if *v < prev => '\\',
+            _ => '_',
+        };
+    }
+    // averaged values only need a decimal place
+    let label = |v: f64| format!("{}", (v * 10.0).round() / 10.0);
+    let (top, bottom) = (label(range.1), label(range.0));
+    let label_width = top.len().max(bottom.len());
+    rows.into_iter()
+        .enumerate()
+        .map(|(r, row)| {
+            let label = match r {
+                0 => top.as_str(),
+                r if r == height - 1 => bottom.as_str(),
+                _ => "",
+            };
+            let line: String = row.into_iter().collect();
+            format!("{:>w$} |{}", label, line, w = label_width).trim_end().to_string()
+        })
+        .collect()
+}
+
+const SVG_WIDTH: f64 = 800.0;
+const SVG_HEIGHT: f64 = 300.0;
+const SVG_MARGIN: f64 = 10.0;
+const INCREASE_COLOUR: &str = "#2a9d3a";
+const DECREASE_COLOUR: &str = "#d62828";
+const FLAT_COLOUR: &str = "#888888";
+
+// a panel per series, one above the other, each with a line segment per step
+// coloured by whether it went up or down
+fn svg_chart(charts: &[(&str, Vec<f64>)]) -> String {
+    let height = SVG_HEIGHT * charts.len() as f64;
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n",
+        w = SVG_WIDTH, h = height);
+    svg += "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>\n";
+    for (panel, (name, series)) in charts.iter().enumerate() {
+        let top = panel as f64 * SVG_HEIGHT;
+        let range = bounds(series);
+        let x = |i: usize| SVG_MARGIN + i as f64 * (SVG_WIDTH - 2.0 * SVG_MARGIN) / (series.len().max(2) - 1) as f64;
+        let y = |v: f64| top + SVG_MARGIN + (1.0 - scaled(v, range)) * (SVG_HEIGHT - 2.0 * SVG_MARGIN);
+        svg += &format!("<text x=\"{:.1}\" y=\"{:.1}\" font-size=\"12\">{}</text>\n", SVG_MARGIN, top + SVG_MARGIN, name);
+        for (i, vals) in series.windows(2).enumerate() {
+            let colour = if vals[1] > vals[0] {
+                INCREASE_COLOUR
+            } else if vals[1] < vals[0] {
+                DECREASE_COLOUR
+            } else {
+                FLAT_COLOUR
+            };
+            svg += &format!("<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\"/>\n",
+                x(i), y(vals[0]), x(i + 1), y(vals[1]), colour);
+        }
+    }
+    svg + "</svg>\n"
+}
+
+fn part_1(lines: impl Iterator<Item = String>) -> usize {
+    count_changes(lines, &Analysis::preset(1))
 }
 
 fn part_2(lines: impl Iterator<Item = String>) -> usize {
-    let depths: Vec<u32> = parse_input(lines).collect();
-    let windowed_sums: Vec<u32> = depths.windows(3).map(|vals| vals.iter().sum()).collect();
-    let increases = windowed_sums
-        .windows(2)
-        .filter(|vals| vals[1] > vals[0])
-        .count();
-    increases
+    count_changes(lines, &Analysis::preset(2))
+}
+
+// --report, --chart, --svg and --stream read a single column of depths, so can't be
+// combined with CSV channels or time windows
+fn single_channel_only(opts: &Opts) -> Result<(), String> {
+    let modes: Vec<_> = [
+        ("--report", opts.report),
+        ("--chart", opts.chart),
+        ("--svg", opts.svg.is_some()),
+        ("--stream", opts.stream),
+    ].iter().filter(|(_, on)| *on).map(|(mode, _)| *mode).collect();
+    if modes.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} can't be combined with CSV channels or --time-window", modes.join(", ")))
+    }
+}
+
+fn usage_error(message: &str) -> ! {
+    clap::Error::with_description(message, clap::ErrorKind::ArgumentConflict).exit()
+}
+
+// the part's preset with any options given on the command line
+fn chosen_analysis(opts: &Opts) -> Analysis {
+    let preset = Analysis::preset(opts.part_no);
+    Analysis {
+        window: opts.window.unwrap_or(preset.window),
+        aggregate: opts.aggregate.unwrap_or(preset.aggregate),
+        compare: opts.compare.unwrap_or(preset.compare),
+    }
 }
 
 fn main() {
     let opts = Opts::from_args();
-    let data = iter_lines(opts.filename);
-    match opts.part_no {
-        1 => println!("{}", part_1(data)),
-        2 => println!("{}", part_2(data)),
-        _ => panic!("Invalid part"),
+    let data: Box<dyn Iterator<Item = String>> = if opts.filename.as_os_str() == "-" {
+        Box::new(io::stdin().lines().map(Result::unwrap))
+    } else {
+        Box::new(iter_lines(opts.filename.clone()))
+    };
+    let mut data = data.peekable();
+    let csv = data.peek().is_some_and(|l| l.trim().parse::<u32>().is_err());
+    if csv || opts.time_window.is_some() {
+        if let Err(e) = single_channel_only(&opts) {
+            usage_error(&e);
+        }
+        if opts.time_window.is_some() && opts.window.is_some() {
+            usage_error("--window and --time-window can't be used together");
+        }
+        let readings = parse_readings(data);
+        if opts.time_window.is_some() && readings.times.is_none() {
+            usage_error("--time-window needs CSV input with a time or timestamp column");
+        }
+        let analysis = chosen_analysis(&opts);
+        for l in channel_counts(&readings, &analysis, opts.time_window) {
+            println!("{}", l);
+        }
+        return;
+    }
+    if opts.report {
+        let depths: Vec<u32> = parse_input(data).collect();
+        for l in report(&depths, opts.median_window, opts.outlier) {
+            println!("{}", l);
+        }
+        return;
+    }
+    if opts.chart || opts.svg.is_some() {
+        let depths: Vec<u32> = parse_input(data).collect();
+        let analysis = chosen_analysis(&opts);
+        let raw: Vec<f64> = depths.iter().map(|d| *d as f64).collect();
+        let mut charts = vec![("depths", raw)];
+        if analysis.window > 1 {
+            charts.push(("windowed", analysis.windowed(&depths)));
+        }
+        if opts.chart {
+            for (name, series) in &charts {
+                println!("{}: {}", name, sparkline(series, opts.width));
+                for l in ascii_chart(series, opts.width, opts.height) {
+                    println!("{}", l);
+                }
+            }
+        }
+        if let Some(path) = opts.svg {
+            std::fs::write(path, svg_chart(&charts)).expect("Unable to write chart");
+        }
+        return;
+    }
+    if !opts.stream && opts.window.is_none() && opts.aggregate.is_none() && opts.compare.is_none() {
+        match opts.part_no {
+            1 => println!("{}", part_1(data)),
+            2 => println!("{}", part_2(data)),
+            _ => panic!("Invalid part"),
+        }
+        return;
+    }
+    let analysis = chosen_analysis(&opts);
+    if opts.stream {
+        println!("{}", count_streaming(parse_input(data), &analysis));
+    } else {
+        println!("{}", count_changes(data, &analysis));
     }
 }
 
@@ -68,4 +621,258 @@ mod test {
     pub fn test_part_2() {
         assert_eq!(5, part_2(test_data()));
     }
+
+    #[test]
+    pub fn test_parse_options() {
+        assert_eq!(Ok(Aggregate::Mean), "mean".parse());
+        assert!("median".parse::<Aggregate>().is_err());
+        assert_eq!(Ok(Comparison::Decrease), "decrease".parse());
+        assert_eq!(Ok(Comparison::AtLeast(10.0)), "at-least:10".parse());
+        assert_eq!(Ok(Comparison::AtLeast(-2.5)), "at-least:-2.5".parse());
+        assert!("at-least:x".parse::<Comparison>().is_err());
+        assert!("at-least".parse::<Comparison>().is_err());
+        assert_eq!(Ok(3), parse_positive("3"));
+        assert!(parse_positive("0").is_err());
+        assert!(parse_positive("-1").is_err());
+        assert!(Opts::from_iter_safe(["day_01", "1", "--window", "0"]).is_err());
+        assert_eq!(Some(1), Opts::from_iter_safe(["day_01", "1", "--window", "1"]).unwrap().window);
+        assert!(Opts::from_iter_safe(["day_01", "1", "--chart", "--width", "0"]).is_err());
+        assert!(Opts::from_iter_safe(["day_01", "1", "--chart", "--height", "0"]).is_err());
+        let opts = Opts::from_iter_safe(["day_01", "1", "--chart", "--width", "1", "--height", "1"]).unwrap();
+        assert_eq!((1, 1), (opts.width, opts.height));
+    }
+
+    #[test]
+    pub fn test_analysis() {
+        let analyse = |window, aggregate, compare| count_changes(test_data(), &Analysis { window, aggregate, compare });
+        // a mean rises exactly when the sum does
+        assert_eq!(5, analyse(3, Aggregate::Mean, Comparison::Increase));
+        assert_eq!(4, analyse(2, Aggregate::Max, Comparison::Increase));
+        assert_eq!(2, analyse(2, Aggregate::Max, Comparison::Decrease));
+        assert_eq!(2, analyse(1, Aggregate::Sum, Comparison::Decrease));
+        assert_eq!(2, analyse(1, Aggregate::Sum, Comparison::AtLeast(10.0)));
+        assert_eq!(7, analyse(1, Aggregate::Sum, Comparison::AtLeast(1.0)));
+        // a window as long as the data leaves nothing to compare
+        assert_eq!(0, analyse(10, Aggregate::Sum, Comparison::Increase));
+    }
+
+    #[test]
+    pub fn test_streaming() {
+        let aggregates = [Aggregate::Sum, Aggregate::Mean, Aggregate::Max];
+        let comparisons = [Comparison::Increase, Comparison::Decrease, Comparison::AtLeast(5.0)];
+        let real: Vec<u32> = parse_input(iter_lines("data/day_01.txt")).collect();
+        for window in 1..=11 {
+            for aggregate in aggregates {
+                for compare in comparisons {
+                    let analysis = Analysis { window, aggregate, compare };
+                    assert_eq!(count_changes(test_data(), &analysis), count_streaming(parse_input(test_data()), &analysis));
+                    assert_eq!(analysis.count(&real), count_streaming(real.iter().copied(), &analysis));
+                }
+            }
+        }
+        assert_eq!(7, count_streaming(parse_input(test_data()), &Analysis::preset(1)));
+        assert_eq!(5, count_streaming(parse_input(test_data()), &Analysis::preset(2)));
+
+        // both paths agree at the edges: the narrowest window the options allow,
+        // a window exactly as long as the data, and one longer than it
+        let depths: Vec<u32> = parse_input(test_data()).collect();
+        for (window, expected) in [(1, 7), (depths.len(), 0), (depths.len() + 1, 0)] {
+            let analysis = Analysis { window, aggregate: Aggregate::Sum, compare: Comparison::Increase };
+            assert_eq!(expected, analysis.count(&depths));
+            assert_eq!(expected, count_changes(test_data(), &analysis));
+            assert_eq!(expected, count_streaming(depths.iter().copied(), &analysis));
+        }
+        assert_eq!(0, count_streaming(std::iter::empty(), &Analysis::preset(1)));
+        assert_eq!(0, Analysis::preset(1).count(&[]));
+
+        // a long run of depths, never collected
+        let sawtooth = (0..1_000_000u32).map(|i| i % 4);
+        assert_eq!(750_000, count_streaming(sawtooth, &Analysis::preset(1)));
+    }
+
+    #[test]
+    pub fn test_runs() {
+        let depths: Vec<u32> = parse_input(test_data()).collect();
+        // the first of the equally long runs
+        assert_eq!(Some((0, 3)), longest_run(&depths, |a, b| b > a));
+        assert_eq!(Some((3, 4)), longest_run(&depths, |a, b| b < a));
+        assert_eq!(None, longest_run(&[5, 5, 5], |a, b| b > a));
+        assert_eq!(Some((1, 4)), longest_run(&[9, 1, 2, 3, 4], |a, b| b > a));
+
+        assert!(plateaus(&depths).is_empty());
+        assert_eq!(vec![(0, 1), (3, 5)], plateaus(&[3, 3, 4, 2, 2, 2, 5]));
+        assert_eq!(vec![(5, 33), (6, 29), (3, -10)], largest_jumps(&depths, 3));
+    }
+
+    #[test]
+    pub fn test_outliers() {
+        assert_eq!(vec![1.5, 2.0, 3.0, 4.0, 4.5], rolling_median(&[1, 2, 3, 4, 5], 3));
+        assert_eq!(vec![2.0, 2.5, 3.0, 3.5, 4.0], rolling_median(&[1, 2, 3, 4, 5], 5));
+        // deep enough that the middle pair would overflow if added as depths
+        assert_eq!(vec![4294967294.0, 4294967293.0, 4294967292.0], rolling_median(&[u32::MAX, u32::MAX - 2, u32::MAX - 4], 3));
+        let depths = [100, 101, 102, 160, 103, 104, 104, 104, 105];
+        assert_eq!(vec![(3, 160, 103.0)], outliers(&depths, 5, 10.0));
+
+        let lines = report(&depths, 5, 10.0);
+        assert_eq!(vec![
+            "longest increasing run: 3 steps, indexes 0 to 3 (100 -> 160)",
+            "longest decreasing run: 1 steps, indexes 3 to 4 (160 -> 103)",
+            "plateau: indexes 5 to 7 at 104",
+            "jump: +58 from index 2 to 3 (102 -> 160)",
+            "jump: -57 from index 3 to 4 (160 -> 103)",
+            "jump: +1 from index 0 to 1 (100 -> 101)",
+            "outlier: index 3 at 160, rolling median 103",
+        ], lines);
+    }
+
+    #[test]
+    pub fn test_downsample() {
+        assert_eq!(vec![1.0, 2.0, 3.0], downsample(&[1.0, 2.0, 3.0], 5));
+        assert_eq!(vec![1.5, 3.5], downsample(&[1.0, 2.0, 3.0, 4.0], 2));
+        assert_eq!(vec![1.0, 2.5], downsample(&[1.0, 2.0, 3.0], 2));
+    }
+
+    #[test]
+    pub fn test_sparkline() {
+        assert_eq!("▁▂▃▄▅▆▇█", sparkline(&[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0], 80));
+        // a flat series sits in the middle
+        assert_eq!("▅▅▅", sparkline(&[4.0, 4.0, 4.0], 80));
+        let depths: Vec<f64> = parse_input(test_data()).map(|d| d as f64).collect();
+        assert_eq!("▁▁▂▂▁▂▅█▇▇", sparkline(&depths, 80));
+        assert_eq!(5, sparkline(&depths, 5).chars().count());
+    }
+
+    #[test]
+    pub fn test_ascii_chart() {
+        let depths: Vec<f64> = parse_input(test_data()).map(|d| d as f64).collect();
+        assert_eq!(vec![
+            "269 |       / /",
+            "    |        \\",
+            "    |      /",
+            "    |  //",
+            "199 |_/  \\/",
+        ], ascii_chart(&depths, 80, 5));
+        // the smallest chart the options allow is one averaged point
+        assert_eq!(vec!["225.6 |_"], ascii_chart(&depths, 1, 1));
+    }
+
+    #[test]
+    pub fn test_svg_chart() {
+        let depths: Vec<u32> = parse_input(test_data()).collect();
+        let svg = svg_chart(&[("windowed", Analysis::preset(2).windowed(&depths))]);
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"800\" height=\"300\""));
+        assert!(svg.ends_with("</svg>\n"));
+        // the windowed sums rise 5 times, fall once and hold once
+        assert_eq!(5, svg.matches(INCREASE_COLOUR).count());
+        assert_eq!(1, svg.matches(DECREASE_COLOUR).count());
+        assert_eq!(1, svg.matches(FLAT_COLOUR).count());
+        // first point at the left margin and the bottom of the range
+        assert!(svg.contains("<line x1=\"10.0\" y1=\"290.0\""));
+
+        // the raw depths in a panel above the windowed sums
+        let raw: Vec<f64> = depths.iter().map(|d| *d as f64).collect();
+        let svg = svg_chart(&[("depths", raw), ("windowed", Analysis::preset(2).windowed(&depths))]);
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"800\" height=\"600\""));
+        assert!(svg.contains(">depths</text>") && svg.contains(">windowed</text>"));
+        assert_eq!((7 + 5, 2 + 1, 1), (svg.matches(INCREASE_COLOUR).count(), svg.matches(DECREASE_COLOUR).count(), svg.matches(FLAT_COLOUR).count()));
+        // the windowed panel starts at the bottom of its own range
+        assert!(svg.contains("<line x1=\"10.0\" y1=\"590.0\""));
+    }
+
+    static CSV_DATA: &str = r"time,port,starboard
+0,199,100
+1,200,
+2,208,102
+3,210,101
+4,200,103
+5,207,104
+6,240
+7,269,90
+8,260,91
+9,263,92";
+
+    fn csv_data() -> impl Iterator<Item = String> {
+        CSV_DATA.lines().map(String::from)
+    }
+
+    #[test]
+    pub fn test_parse_readings() {
+        let readings = parse_readings(csv_data());
+        assert_eq!(Some((0..10).map(|t| t as f64).collect()), readings.times);
+        assert_eq!(vec!["port", "starboard"], readings.channels.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>());
+        assert_eq!(parse_input(test_data()).map(Some).collect::<Vec<_>>(), readings.channels[0].1);
+        assert_eq!(vec![Some(100), None, Some(102)], readings.channels[1].1[..3]);
+        assert_eq!(None, readings.channels[1].1[6]);
+
+        // the original format is a single channel with no times
+        let readings = parse_readings(test_data());
+        assert_eq!(None, readings.times);
+        assert_eq!(1, readings.channels.len());
+        assert_eq!(10, readings.channels[0].1.len());
+
+        // the time column can go anywhere, or nowhere
+        let readings = parse_readings("depth,Timestamp\n5,10\n6,20".lines().map(String::from));
+        assert_eq!(Some(vec![10.0, 20.0]), readings.times);
+        assert_eq!(vec![("depth".to_string(), vec![Some(5), Some(6)])], readings.channels);
+        assert_eq!(None, parse_readings("a,b\n1,2".lines().map(String::from)).times);
+    }
+
+    #[test]
+    pub fn test_channels() {
+        let readings = parse_readings(csv_data());
+        assert_eq!(vec!["port: 7", "starboard: 5"], channel_counts(&readings, &Analysis::preset(1), None));
+        assert_eq!(vec!["port: 5", "starboard: 2"], channel_counts(&readings, &Analysis::preset(2), None));
+        let original = parse_readings(test_data());
+        assert_eq!(vec!["depth: 7"], channel_counts(&original, &Analysis::preset(1), None));
+    }
+
+    #[test]
+    pub fn test_single_channel_only() {
+        let opts = |args: &[&str]| Opts::from_iter_safe(["day_01", "1"].iter().chain(args)).unwrap();
+        assert_eq!(Ok(()), single_channel_only(&opts(&["--time-window", "2"])));
+        assert_eq!(Err("--report can't be combined with CSV channels or --time-window".to_string()),
+            single_channel_only(&opts(&["--report"])));
+        assert_eq!(Err("--chart, --svg, --stream can't be combined with CSV channels or --time-window".to_string()),
+            single_channel_only(&opts(&["--stream", "--chart", "--svg", "out.svg"])));
+    }
+
+    #[test]
+    pub fn test_time_windows() {
+        let readings = parse_readings(csv_data());
+        let times = readings.times.as_ref().unwrap();
+        assert_eq!(vec![399.0, 408.0, 418.0, 410.0, 407.0, 447.0, 509.0, 529.0, 523.0],
+            time_windowed(times, &readings.channels[0].1, 2.0, Aggregate::Sum));
+        // empty cells are left out of the windows they fall in
+        assert_eq!(vec![102.0, 101.5, 102.0, 103.5, 90.0, 90.5, 91.5],
+            time_windowed(times, &readings.channels[1].1, 2.0, Aggregate::Mean));
+        assert_eq!(vec![100.0, 102.0], time_windowed(&[0.0, 1.0, 5.0], &[Some(100), None, Some(102)], 1.0, Aggregate::Max));
+        // too few times to tell the sampling interval
+        assert!(time_windowed(&[0.0], &[Some(100)], 1.0, Aggregate::Max).is_empty());
+
+        let analysis = Analysis { window: 1, aggregate: Aggregate::Mean, compare: Comparison::Increase };
+        assert_eq!(vec!["port: 5", "starboard: 4"], channel_counts(&readings, &analysis, Some(2.0)));
+        // one reading per second is the same as no window
+        assert_eq!(vec!["port: 7", "starboard: 5"], channel_counts(&readings, &analysis, Some(1.0)));
+
+        // at one reading per second, n seconds slide just as n depths do
+        let depths: Vec<u32> = parse_input(test_data()).collect();
+        let times: Vec<f64> = (0..depths.len()).map(|t| t as f64).collect();
+        let cells: Vec<_> = depths.iter().copied().map(Some).collect();
+        for window in 1..=11 {
+            let analysis = Analysis { window, aggregate: Aggregate::Sum, compare: Comparison::Increase };
+            assert_eq!(analysis.windowed(&depths), time_windowed(&times, &cells, window as f64, Aggregate::Sum));
+        }
+
+        assert_eq!(Ok(0.5), parse_seconds("0.5"));
+        assert!(parse_seconds("0").is_err());
+        assert!(parse_seconds("-1").is_err());
+        assert!(parse_seconds("inf").is_err());
+        assert!(Opts::from_iter_safe(["day_01", "1", "--time-window", "0"]).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Time windows need a time column")]
+    pub fn test_time_windows_without_times() {
+        channel_counts(&parse_readings(test_data()), &Analysis::preset(1), Some(2.0));
+    }
 }
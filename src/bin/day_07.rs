@@ -1,6 +1,7 @@
-use std::path::PathBuf;
+use std::{convert::TryFrom, path::PathBuf, ops::RangeInclusive, str::FromStr};
 use advent_of_code_2021::iter_lines;
-use structopt::StructOpt;
+use itertools::Itertools;
+use structopt::{clap, StructOpt};
 
 const DATA_FILE: &str = "data/day_07.txt";
 
@@ -10,6 +11,31 @@ struct Opts {
 
     #[structopt(parse(from_os_str), default_value = DATA_FILE)]
     filename: PathBuf,
+
+    /// Fuel cost: linear, triangular, quadratic or an expression of the distance n
+    #[structopt(long, parse(try_from_str = parse_cost))]
+    cost: Option<Box<dyn FuelCost>>,
+
+    /// Comma separated per-crab multipliers applied to the cost
+    #[structopt(long, use_delimiter = true)]
+    weights: Vec<i64>,
+
+    /// Align to this many positions instead of one, at least 1 (part 1's linear cost on a line only)
+    #[structopt(long, parse(try_from_str = parse_targets))]
+    targets: Option<usize>,
+
+    /// Read one x,y position per line and align on a grid
+    #[structopt(long)]
+    grid: bool,
+
+    /// Grid fuel: manhattan, euclidean-squared or chebyshev (default is the 1-D cost per axis);
+    /// replaces --cost and --weights, so can't be combined with them
+    #[structopt(long)]
+    metric: Option<Metric>,
+
+    /// Print the per-crab fuel and the cost curve as CSV (not with --grid)
+    #[structopt(long)]
+    explain: bool,
 }
 
 fn parse_input(lines: impl Iterator<Item = String>) -> Vec<i32> {
@@ -20,37 +46,635 @@ fn parse_input(lines: impl Iterator<Item = String>) -> Vec<i32> {
         .collect()
 }
 
-fn calc_fuel(positions: &Vec<i32>, d: i32) -> Vec<i32> {
-    positions.iter().map(|p| (p - d ).abs()).collect()
+fn parse_grid_input(lines: impl Iterator<Item = String>) -> Vec<(i32, i32)> {
+    lines.into_iter()
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| {
+            let (x, y) = s.trim().split_once(",").unwrap();
+            (x.trim().parse().unwrap(), y.trim().parse().unwrap())
+        })
+        .collect()
 }
 
-fn part_1(lines: impl Iterator<Item = String>) -> i32 {
-    let positions = parse_input(lines);
-    (0..positions.len())
-        .map(|d| calc_fuel(&positions, d as i32).iter().sum() )
-        .min().unwrap()    
+fn parse_targets(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err("Must be at least 1".to_string()),
+        Ok(k) => Ok(k),
+        Err(_) => Err(format!("Invalid number of targets '{}'", s)),
+    }
+}
+
+fn search_range(positions: &[i32]) -> RangeInclusive<i32> {
+    // candidate alignment positions span the crabs, wherever they are
+    let min = *positions.iter().min().unwrap();
+    let max = *positions.iter().max().unwrap();
+    min..=max
+}
+
+trait FuelCost {
+    // fuel used by crab number `crab` to move `n` steps
+    fn cost(&self, crab: usize, n: i64) -> i64;
+
+    // true if the total fuel is convex in the alignment position, so the
+    // minimum can be found by bisection rather than trying every position
+    fn is_convex(&self) -> bool;
+
+    // an error if moving up to max_distance steps costs more than can be
+    // added up over this many crabs
+    fn check(&self, _crabs: usize, _max_distance: i64) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+struct Linear;
+
+impl FuelCost for Linear {
+    fn cost(&self, _crab: usize, n: i64) -> i64 {
+        n
+    }
+
+    fn is_convex(&self) -> bool {
+        true
+    }
+}
+
+struct Triangular;
+
+impl FuelCost for Triangular {
+    fn cost(&self, _crab: usize, n: i64) -> i64 {
+        n*(n+1)/2
+    }
+
+    fn is_convex(&self) -> bool {
+        true
+    }
+}
+
+struct Quadratic;
+
+impl FuelCost for Quadratic {
+    fn cost(&self, _crab: usize, n: i64) -> i64 {
+        n*n
+    }
+
+    fn is_convex(&self) -> bool {
+        true
+    }
+}
+
+// scales another cost by a per-crab weight
+struct Weighted {
+    weights: Vec<i64>,
+    inner: Box<dyn FuelCost>,
+}
+
+impl FuelCost for Weighted {
+    fn cost(&self, crab: usize, n: i64) -> i64 {
+        let w = self.weights.get(crab).expect("Need one weight per crab");
+        w * self.inner.cost(crab, n)
+    }
+
+    fn is_convex(&self) -> bool {
+        self.inner.is_convex() && self.weights.iter().all(|w| *w >= 0)
+    }
+
+    fn check(&self, crabs: usize, max_distance: i64) -> Result<(), String> {
+        self.inner.check(crabs, max_distance)?;
+        for n in 0..=max_distance {
+            for (crab, w) in self.weights.iter().enumerate() {
+                w.checked_mul(self.inner.cost(crab, n))
+                    .and_then(|c| c.checked_mul(crabs as i64))
+                    .ok_or_else(|| format!("Weighted cost of crab {} overflows at n = {}", crab, n))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// arbitrary integer expression of the distance `n`, e.g. "n*(n+1)/2"
+#[derive(Debug, PartialEq)]
+enum Expr {
+    Num(i64),
+    Dist,
+    Neg(Box<Expr>),
+    Op(char, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, n: i64) -> Result<i64, String> {
+        let overflow = || format!("Cost expression overflows at n = {}", n);
+        match self {
+            Expr::Num(v) => Ok(*v),
+            Expr::Dist => Ok(n),
+            Expr::Neg(e) => e.eval(n)?.checked_neg().ok_or_else(overflow),
+            Expr::Op(op, a, b) => {
+                let (a, b) = (a.eval(n)?, b.eval(n)?);
+                match op {
+                    '+' => a.checked_add(b).ok_or_else(overflow),
+                    '-' => a.checked_sub(b).ok_or_else(overflow),
+                    '*' => a.checked_mul(b).ok_or_else(overflow),
+                    '/' | '%' if b == 0 => Err(format!("Division by zero in cost expression at n = {}", n)),
+                    '/' => a.checked_div(b).ok_or_else(overflow),
+                    '%' => a.checked_rem(b).ok_or_else(overflow),
+                    '^' => {
+                        let b = u32::try_from(b).map_err(|_| format!("Exponent {} out of range in cost expression at n = {}", b, n))?;
+                        a.checked_pow(b).ok_or_else(overflow)
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
 }
 
-fn calc_fuel2(positions: &Vec<i32>, d: i32) -> Vec<i32> {
-    positions.iter().map(|p| (p - d ).abs())
-        .map(|n| n*(n+1)/2).collect()
+// recursive descent over:
+//   expr  := term (('+' | '-') term)*
+//   term  := power (('*' | '/' | '%') power)*
+//   power := unary ('^' power)?
+//   unary := '-' unary | 'n' | number | '(' expr ')'
+struct ExprParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
 }
 
-fn part_2(lines: impl Iterator<Item = String>) -> i32 {
+impl<'a> ExprParser<'a> {
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+        self.chars.peek().copied()
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut e = self.term()?;
+        while let Some(op) = self.peek().filter(|c| "+-".contains(*c)) {
+            self.chars.next();
+            e = Expr::Op(op, Box::new(e), Box::new(self.term()?));
+        }
+        Ok(e)
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut e = self.power()?;
+        while let Some(op) = self.peek().filter(|c| "*/%".contains(*c)) {
+            self.chars.next();
+            e = Expr::Op(op, Box::new(e), Box::new(self.power()?));
+        }
+        Ok(e)
+    }
+
+    fn power(&mut self) -> Result<Expr, String> {
+        let e = self.unary()?;
+        if self.peek() == Some('^') {
+            self.chars.next();
+            return Ok(Expr::Op('^', Box::new(e), Box::new(self.power()?)));
+        }
+        Ok(e)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some('-') => {
+                self.chars.next();
+                Ok(Expr::Neg(Box::new(self.unary()?)))
+            }
+            Some('n') => {
+                self.chars.next();
+                Ok(Expr::Dist)
+            }
+            Some('(') => {
+                self.chars.next();
+                let e = self.expr()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.chars.next();
+                        Ok(e)
+                    }
+                    _ => Err("Expected ')'".into()),
+                }
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut v = 0i64;
+                while let Some(d) = self.chars.next_if(char::is_ascii_digit) {
+                    v = v.checked_mul(10)
+                        .and_then(|v| v.checked_add(d.to_digit(10).unwrap() as i64))
+                        .ok_or("Number too large")?;
+                }
+                Ok(Expr::Num(v))
+            }
+            Some(c) => Err(format!("Unexpected '{}'", c)),
+            None => Err("Unexpected end of expression".into()),
+        }
+    }
+}
+
+fn parse_expr(s: &str) -> Result<Expr, String> {
+    let mut parser = ExprParser { chars: s.chars().peekable() };
+    let e = parser.expr()?;
+    match parser.peek() {
+        None => Ok(e),
+        Some(c) => Err(format!("Unexpected '{}'", c)),
+    }
+}
+
+impl FuelCost for Expr {
+    // checked up front, so only fails if check was skipped
+    fn cost(&self, _crab: usize, n: i64) -> i64 {
+        self.eval(n).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // can't tell in general, so always search exhaustively
+    fn is_convex(&self) -> bool {
+        false
+    }
+
+    fn check(&self, crabs: usize, max_distance: i64) -> Result<(), String> {
+        for n in 0..=max_distance {
+            self.eval(n)?
+                .checked_mul(crabs as i64)
+                .ok_or_else(|| format!("Cost expression too large to total at n = {}", n))?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_cost(s: &str) -> Result<Box<dyn FuelCost>, String> {
+    match s {
+        "linear" => Ok(Box::new(Linear)),
+        "triangular" => Ok(Box::new(Triangular)),
+        "quadratic" => Ok(Box::new(Quadratic)),
+        _ => Ok(Box::new(parse_expr(s)?)),
+    }
+}
+
+fn total_fuel(positions: &[i32], cost: &dyn FuelCost, d: i32) -> i64 {
+    positions.iter()
+        .enumerate()
+        .map(|(i, p)| cost.cost(i, (p - d).abs() as i64))
+        .sum()
+}
+
+// returns (position, fuel) of the cheapest alignment, trying every candidate
+fn brute_force(positions: &[i32], cost: &dyn FuelCost) -> (i32, i64) {
+    search_range(positions)
+        .map(|d| (d, total_fuel(positions, cost, d)))
+        .min_by_key(|(_, fuel)| *fuel)
+        .unwrap()
+}
+
+// returns (position, fuel) of the cheapest alignment, bisecting on the slope
+//  only valid for convex costs, where the first position at which the fuel
+//  stops decreasing is the minimum
+fn convex_search(positions: &[i32], cost: &dyn FuelCost) -> (i32, i64) {
+    let range = search_range(positions);
+    let (mut lo, mut hi) = (*range.start(), *range.end());
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if total_fuel(positions, cost, mid + 1) < total_fuel(positions, cost, mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    (lo, total_fuel(positions, cost, lo))
+}
+
+fn solve(positions: &[i32], cost: &dyn FuelCost) -> (i32, i64) {
+    if cost.is_convex() {
+        convex_search(positions, cost)
+    } else {
+        brute_force(positions, cost)
+    }
+}
+
+// CSV breakdown of the best alignment, for checking cost functions:
+//  the chosen position, then each crab's distance and fuel, then the total
+//  fuel for every candidate position
+fn explain(positions: &[i32], cost: &dyn FuelCost) -> Vec<String> {
+    let (pos, fuel) = solve(positions, cost);
+    let mut out = vec![
+        "position,fuel".to_string(),
+        format!("{},{}", pos, fuel),
+        String::new(),
+        "crab,position,distance,fuel".to_string(),
+    ];
+    for (i, p) in positions.iter().enumerate() {
+        let n = (p - pos).abs() as i64;
+        out.push(format!("{},{},{},{}", i, p, n, cost.cost(i, n)));
+    }
+    out.push(String::new());
+    out.push("position,fuel".to_string());
+    for d in search_range(positions) {
+        out.push(format!("{},{}", d, total_fuel(positions, cost, d)));
+    }
+    out
+}
+
+fn part_1(lines: impl Iterator<Item = String>) -> (i32, i64) {
+    let positions = parse_input(lines);
+    solve(&positions, &Linear)
+}
+
+fn part_2(lines: impl Iterator<Item = String>) -> (i32, i64) {
     let positions = parse_input(lines);
-    (0..positions.len())
-        .map(|d| calc_fuel2(&positions, d as i32).iter().sum() )
-        .min().unwrap()
+    solve(&positions, &Triangular)
+}
+
+trait GridCost {
+    // fuel used by crab number `crab` to move `dx` across and `dy` down
+    fn cost(&self, crab: usize, dx: i64, dy: i64) -> i64;
+
+    // the 1-D costs along x and y, if the total is just their sum
+    fn axes(&self) -> Option<(&dyn FuelCost, &dyn FuelCost)> {
+        None
+    }
+}
+
+// applies a 1-D cost independently along each axis
+struct PerAxis(Box<dyn FuelCost>);
+
+impl GridCost for PerAxis {
+    fn cost(&self, crab: usize, dx: i64, dy: i64) -> i64 {
+        self.0.cost(crab, dx) + self.0.cost(crab, dy)
+    }
+
+    fn axes(&self) -> Option<(&dyn FuelCost, &dyn FuelCost)> {
+        Some((self.0.as_ref(), self.0.as_ref()))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Metric {
+    Manhattan,
+    EuclideanSquared,
+    Chebyshev,
+}
+
+impl FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "manhattan" => Ok(Metric::Manhattan),
+            "euclidean-squared" => Ok(Metric::EuclideanSquared),
+            "chebyshev" => Ok(Metric::Chebyshev),
+            _ => Err(format!("Unknown metric '{}'", s)),
+        }
+    }
+}
+
+impl GridCost for Metric {
+    fn cost(&self, _crab: usize, dx: i64, dy: i64) -> i64 {
+        match self {
+            Metric::Manhattan => dx + dy,
+            Metric::EuclideanSquared => dx*dx + dy*dy,
+            Metric::Chebyshev => dx.max(dy),
+        }
+    }
+
+    fn axes(&self) -> Option<(&dyn FuelCost, &dyn FuelCost)> {
+        match self {
+            Metric::Manhattan => Some((&Linear, &Linear)),
+            Metric::EuclideanSquared => Some((&Quadratic, &Quadratic)),
+            Metric::Chebyshev => None,
+        }
+    }
+}
+
+fn total_grid_fuel(positions: &[(i32, i32)], cost: &dyn GridCost, (x, y): (i32, i32)) -> i64 {
+    positions.iter()
+        .enumerate()
+        .map(|(i, (px, py))| cost.cost(i, (px - x).abs() as i64, (py - y).abs() as i64))
+        .sum()
+}
+
+// returns (position, fuel) of the cheapest grid point, trying the whole bounding box
+fn brute_force_grid(positions: &[(i32, i32)], cost: &dyn GridCost) -> ((i32, i32), i64) {
+    let xs = positions.iter().map(|p| p.0).collect::<Vec<_>>();
+    let ys = positions.iter().map(|p| p.1).collect::<Vec<_>>();
+    search_range(&xs)
+        .cartesian_product(search_range(&ys))
+        .map(|d| (d, total_grid_fuel(positions, cost, d)))
+        .min_by_key(|(_, fuel)| *fuel)
+        .unwrap()
+}
+
+// a separable cost splits into independent 1-D problems along each axis
+fn solve_grid(positions: &[(i32, i32)], cost: &dyn GridCost) -> ((i32, i32), i64) {
+    match cost.axes() {
+        Some((cx, cy)) => {
+            let xs = positions.iter().map(|p| p.0).collect::<Vec<_>>();
+            let ys = positions.iter().map(|p| p.1).collect::<Vec<_>>();
+            let (x, fx) = solve(&xs, cx);
+            let (y, fy) = solve(&ys, cy);
+            ((x, y), fx + fy)
+        }
+        None => brute_force_grid(positions, cost),
+    }
+}
+
+struct Alignment {
+    fuel: i64,
+    // chosen alignment positions, in ascending order
+    targets: Vec<i32>,
+    // index into targets for each crab, in input order
+    assignment: Vec<usize>,
+}
+
+// 1-D k-medians by dynamic programming
+//  with the crabs sorted, each target serves a contiguous run of them and the
+//  cheapest position for a run is its median, so the best split into k runs
+//  can be built up from the best splits into k-1 runs
+fn k_medians(positions: &[i32], k: usize) -> Alignment {
+    let n = positions.len();
+    if n == 0 {
+        return Alignment { fuel: 0, targets: vec![], assignment: vec![] };
+    }
+    let k = k.clamp(1, n);
+    let mut order = (0..n).collect::<Vec<_>>();
+    order.sort_by_key(|i| positions[*i]);
+    let xs = order.iter().map(|i| positions[*i] as i64).collect::<Vec<_>>();
+    let mut prefix = vec![0; n+1];
+    for (i, x) in xs.iter().enumerate() {
+        prefix[i+1] = prefix[i] + x;
+    }
+
+    // fuel for the sorted crabs [i, j) to align to their median
+    let run_cost = |i: usize, j: usize| {
+        let m = (i + j - 1) / 2;
+        let below = xs[m] * (m - i) as i64 - (prefix[m] - prefix[i]);
+        let above = (prefix[j] - prefix[m+1]) - xs[m] * (j - m - 1) as i64;
+        below + above
+    };
+
+    // best[c][j] is the cheapest way to cover the first j crabs with c targets
+    //  and start[c][j] where the last of those runs begins
+    let mut best = vec![vec![i64::MAX; n+1]; k+1];
+    let mut start = vec![vec![0; n+1]; k+1];
+    best[0][0] = 0;
+    for c in 1..=k {
+        for j in c..=n {
+            for i in c-1..j {
+                if best[c-1][i] == i64::MAX {
+                    continue;
+                }
+                let fuel = best[c-1][i] + run_cost(i, j);
+                if fuel < best[c][j] {
+                    best[c][j] = fuel;
+                    start[c][j] = i;
+                }
+            }
+        }
+    }
+
+    // walk back through the runs to recover the targets
+    let mut targets = vec![0; k];
+    let mut assignment = vec![0; n];
+    let mut j = n;
+    for c in (1..=k).rev() {
+        let i = start[c][j];
+        targets[c-1] = xs[(i + j - 1) / 2] as i32;
+        for crab in &order[i..j] {
+            assignment[*crab] = c-1;
+        }
+        j = i;
+    }
+    Alignment { fuel: best[k][n], targets, assignment }
+}
+
+fn part_cost(part_no: usize) -> Box<dyn FuelCost> {
+    match part_no {
+        1 => Box::new(Linear),
+        2 => Box::new(Triangular),
+        _ => panic!("Invalid part"),
+    }
+}
+
+// the cost selected on the command line, if it differs from the part's own
+fn custom_cost(part_no: usize, cost: Option<Box<dyn FuelCost>>, weights: Vec<i64>) -> Option<Box<dyn FuelCost>> {
+    if weights.is_empty() {
+        return cost;
+    }
+    let inner = cost.unwrap_or_else(|| part_cost(part_no));
+    Some(Box::new(Weighted { weights, inner }))
+}
+
+fn check_weights(weights: &[i64], crabs: usize) -> Result<(), String> {
+    if weights.is_empty() || weights.len() == crabs {
+        Ok(())
+    } else {
+        Err(format!("Got {} weights for {} crabs", weights.len(), crabs))
+    }
+}
+
+// the furthest any crab can move when aligning within the range of positions
+fn max_distance(positions: impl Iterator<Item = i32> + Clone) -> i64 {
+    match positions.clone().minmax().into_option() {
+        Some((lo, hi)) => (hi - lo) as i64,
+        None => 0,
+    }
+}
+
+// an error naming whichever of the other options are set, as mode can't be combined with them
+fn conflicts(mode: &str, others: &[(&str, bool)]) -> Result<(), String> {
+    let set: Vec<_> = others.iter().filter(|(_, on)| *on).map(|(o, _)| *o).collect();
+    if set.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("{} can't be combined with {}", mode, set.join(", ")))
+    }
+}
+
+fn invalid_input(message: &str) -> ! {
+    clap::Error::with_description(message, clap::ErrorKind::ValueValidation).exit()
 }
 
 fn main() {
     let opts = Opts::from_args();
     let data = iter_lines(opts.filename);
-    match opts.part_no {
-        1 => println!("{}", part_1(data)),
-        2 => println!("{}", part_2(data)),
-        _ => panic!("Invalid part"),
+    if let Some(k) = opts.targets {
+        let part = format!("part {}", opts.part_no);
+        let combined = conflicts("--targets", &[
+            (&part, opts.part_no != 1),
+            ("--cost", opts.cost.is_some()),
+            ("--weights", !opts.weights.is_empty()),
+            ("--grid", opts.grid),
+            ("--metric", opts.metric.is_some()),
+            ("--explain", opts.explain),
+        ]);
+        if let Err(e) = combined {
+            invalid_input(&e);
+        }
+        let positions = parse_input(data);
+        let alignment = k_medians(&positions, k);
+        println!("{} (positions {})", alignment.fuel, alignment.targets.iter().join(","));
+        for (p, t) in positions.iter().zip(alignment.assignment) {
+            println!("{} -> {}", p, alignment.targets[t]);
+        }
+        return;
     }
+    if opts.metric.is_some() && !opts.grid {
+        invalid_input("--metric needs --grid");
+    }
+    if opts.grid {
+        let metric = opts.metric.is_some();
+        let combined = conflicts("--grid", &[("--explain", opts.explain)]).and(conflicts("--metric", &[
+            ("--cost", metric && opts.cost.is_some()),
+            ("--weights", metric && !opts.weights.is_empty()),
+        ]));
+        if let Err(e) = combined {
+            invalid_input(&e);
+        }
+        let positions = parse_grid_input(data);
+        if let Err(e) = check_weights(&opts.weights, positions.len()) {
+            invalid_input(&e);
+        }
+        let cost: Box<dyn GridCost> = match opts.metric {
+            Some(m) => Box::new(m),
+            None => {
+                let part_no = opts.part_no;
+                let cost = custom_cost(part_no, opts.cost, opts.weights);
+                Box::new(PerAxis(cost.unwrap_or_else(|| part_cost(part_no))))
+            }
+        };
+        if let Some((x_cost, _)) = cost.axes() {
+            let furthest = max_distance(positions.iter().map(|p| p.0)).max(max_distance(positions.iter().map(|p| p.1)));
+            // both axes are added together
+            if let Err(e) = x_cost.check(2 * positions.len(), furthest) {
+                invalid_input(&e);
+            }
+        }
+        let ((x, y), fuel) = solve_grid(&positions, cost.as_ref());
+        println!("{} (position {},{})", fuel, x, y);
+        return;
+    }
+    let part_no = opts.part_no;
+    if !opts.explain && opts.cost.is_none() && opts.weights.is_empty() {
+        let (pos, fuel) = match part_no {
+            1 => part_1(data),
+            2 => part_2(data),
+            _ => panic!("Invalid part"),
+        };
+        println!("{} (position {})", fuel, pos);
+        return;
+    }
+
+    let positions = parse_input(data);
+    if let Err(e) = check_weights(&opts.weights, positions.len()) {
+        invalid_input(&e);
+    }
+    let cost = custom_cost(part_no, opts.cost, opts.weights).unwrap_or_else(|| part_cost(part_no));
+    if let Err(e) = cost.check(positions.len(), max_distance(positions.iter().copied())) {
+        invalid_input(&e);
+    }
+    if opts.explain {
+        for l in explain(&positions, cost.as_ref()) {
+            println!("{}", l);
+        }
+        return;
+    }
+    let (pos, fuel) = match part_no {
+        1..=2 => solve(&positions, cost.as_ref()),
+        _ => panic!("Invalid part"),
+    };
+    println!("{} (position {})", fuel, pos);
 }
 
 #[cfg(test)]
@@ -75,21 +699,183 @@ mod test {
 
     #[test]
     pub fn test_part_1() {
-        assert_eq!(37, part_1(test_data()));
+        assert_eq!((2, 37), part_1(test_data()));
     }
 
     #[test]
     pub fn test_part_2() {
-        assert_eq!(168, part_2(test_data()));
+        assert_eq!((5, 168), part_2(test_data()));
+    }
+
+    #[test]
+    pub fn test_search_range() {
+        // positions well beyond the number of crabs
+        let far = || "100,105,110".lines().map(String::from);
+        assert_eq!((105, 10), part_1(far()));
+        assert_eq!((105, 30), part_2(far()));
+
+        // negative positions
+        let neg = || "-5,-3,-10".lines().map(String::from);
+        assert_eq!((-5, 7), part_1(neg()));
+        assert_eq!((-6, 17), part_2(neg()));
+    }
+
+    #[test]
+    pub fn test_expr() {
+        let e = parse_expr("n * (n + 1) / 2").unwrap();
+        assert_eq!(Ok(10), e.eval(4));
+        assert_eq!(Ok(-7), parse_expr("-2^3 + 1").unwrap().eval(0));
+        assert_eq!(Ok(1), parse_expr("10 - 3 - 6").unwrap().eval(0));
+        assert!(parse_expr("n +").is_err());
+        assert!(parse_expr("(n").is_err());
+        assert!(parse_expr("n x").is_err());
+        assert!(parse_expr("99999999999999999999").is_err());
+    }
+
+    #[test]
+    pub fn test_expr_errors() {
+        let eval = |s: &str, n| parse_expr(s).unwrap().eval(n);
+        assert_eq!(Err("Cost expression overflows at n = 20".to_string()), eval("n^n", 20));
+        assert_eq!(Err("Exponent -1 out of range in cost expression at n = 1".to_string()), eval("2^(0-n)", 1));
+        assert_eq!(Err("Division by zero in cost expression at n = 0".to_string()), eval("1/n", 0));
+        assert!(eval("(0-9223372036854775807-1)/(0-1)", 0).is_err());
+        assert!(eval("-(0-9223372036854775807-1)", 0).is_err());
+
+        // fine for a single move, but not once added up
+        let positions = parse_input(real_data());
+        let furthest = max_distance(positions.iter().copied());
+        assert!(parse_cost("n^n").unwrap().check(positions.len(), furthest).is_err());
+        assert!(parse_cost("n^4").unwrap().check(positions.len(), furthest).is_ok());
+        assert!(parse_cost("n*4611686018427387").unwrap().check(1, 1000).is_ok());
+        assert!(parse_cost("n*4611686018427387").unwrap().check(2000, 1000).is_err());
+    }
+
+    #[test]
+    pub fn test_costs() {
+        let positions = parse_input(test_data());
+        // convex search and brute force agree for the convex costs
+        let convex: Vec<Box<dyn FuelCost>> = vec![Box::new(Linear), Box::new(Triangular), Box::new(Quadratic)];
+        for cost in convex {
+            assert_eq!(brute_force(&positions, cost.as_ref()), convex_search(&positions, cost.as_ref()));
+        }
+        assert_eq!((5, 291), solve(&positions, &Quadratic));
+
+        // an expression matching the puzzle cost gives the same answer
+        let tri = parse_cost("n*(n+1)/2").unwrap();
+        assert!(!tri.is_convex());
+        assert_eq!((5, 168), solve(&positions, tri.as_ref()));
+
+        // non-convex costs get searched exhaustively
+        let stepped = parse_cost("n % 3").unwrap();
+        assert_eq!(brute_force(&positions, stepped.as_ref()), solve(&positions, stepped.as_ref()));
+    }
+
+    #[test]
+    pub fn test_weighted() {
+        // the heavy crab at 16 drags the target over to it
+        let positions = parse_input(test_data());
+        let mut weights = vec![1; positions.len()];
+        weights[0] = 100;
+        let cost = Weighted { weights, inner: Box::new(Linear) };
+        assert!(cost.is_convex());
+        assert_eq!((16, 111), solve(&positions, &cost));
+
+        let cost = Weighted { weights: vec![-1; positions.len()], inner: Box::new(Linear) };
+        assert!(!cost.is_convex());
+
+        // a weight per crab
+        assert_eq!(Ok(()), check_weights(&[], positions.len()));
+        assert_eq!(Ok(()), check_weights(&[1; 10], positions.len()));
+        assert_eq!(Err("Got 3 weights for 10 crabs".to_string()), check_weights(&[1, 2, 3], positions.len()));
+        let cost = Weighted { weights: vec![i64::MAX; positions.len()], inner: Box::new(Linear) };
+        assert!(cost.check(positions.len(), 16).is_err());
+    }
+
+    #[test]
+    pub fn test_k_medians() {
+        let positions = parse_input(test_data());
+        let one = k_medians(&positions, 1);
+        assert_eq!((37, vec![2]), (one.fuel, one.targets));
+
+        // the two stragglers get their own target
+        let two = k_medians(&positions, 2);
+        assert_eq!((13, vec![2, 14]), (two.fuel, two.targets));
+        assert_eq!(vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 1], two.assignment);
+
+        // agrees with trying every split of the sorted crabs in two
+        let mut sorted = positions.clone();
+        sorted.sort();
+        let split_best = (1..sorted.len())
+            .map(|i| solve(&sorted[..i], &Linear).1 + solve(&sorted[i..], &Linear).1)
+            .min().unwrap();
+        assert_eq!(split_best, two.fuel);
+
+        // a target per distinct position needs no fuel
+        assert_eq!(0, k_medians(&positions, 7).fuel);
+        assert_eq!(0, k_medians(&positions, 100).fuel);
+        // nothing to align
+        let none = k_medians(&[], 2);
+        assert_eq!((0, 0, 0), (none.fuel, none.targets.len(), none.assignment.len()));
+
+        assert_eq!(Ok(2), parse_targets("2"));
+        assert!(parse_targets("0").is_err());
+        assert!(Opts::from_iter_safe(["day_07", "1", "--targets", "0"]).is_err());
+        assert_eq!(Ok(()), conflicts("--targets", &[("--grid", false), ("--cost", false)]));
+        assert_eq!(Err("--targets can't be combined with part 2, --grid".to_string()),
+            conflicts("--targets", &[("part 2", true), ("--cost", false), ("--grid", true)]));
+    }
+
+    #[test]
+    pub fn test_explain() {
+        let positions = parse_input(test_data());
+        let out = explain(&positions, &Triangular);
+        assert_eq!(vec!["position,fuel", "5,168", "", "crab,position,distance,fuel", "0,16,11,66"], out[0..5]);
+        assert_eq!("9,14,9,45", out[13]);
+        // curve over 0..=16 follows the crabs
+        assert_eq!(vec!["", "position,fuel", "0,290"], out[14..17]);
+        assert_eq!("5,168", out[21]);
+        let curve = &out[16..];
+        assert_eq!(17, curve.len());
+        assert_eq!("16,817", curve[16]);
+    }
+
+    static GRID_DATA: &str = r"0,0
+    4,0
+    0,4
+    4,4
+    2,2
+    9,1";
+
+    #[test]
+    pub fn test_grid() {
+        let positions = parse_grid_input(GRID_DATA.lines().map(String::from));
+        assert_eq!(vec![(0, 0), (4, 0), (0, 4), (4, 4), (2, 2), (9, 1)], positions);
+
+        assert_eq!(((2, 1), 24), solve_grid(&positions, &Metric::Manhattan));
+        assert_eq!(((3, 2), 74), solve_grid(&positions, &Metric::EuclideanSquared));
+        assert_eq!(((2, 2), 15), solve_grid(&positions, &Metric::Chebyshev));
+
+        // splitting separable costs by axis matches searching the whole grid
+        let costs: Vec<Box<dyn GridCost>> = vec![
+            Box::new(Metric::Manhattan),
+            Box::new(Metric::EuclideanSquared),
+            Box::new(PerAxis(Box::new(Triangular))),
+        ];
+        for cost in costs {
+            assert!(cost.axes().is_some());
+            assert_eq!(brute_force_grid(&positions, cost.as_ref()), solve_grid(&positions, cost.as_ref()));
+        }
+        assert_eq!("chebyshev".parse(), Ok(Metric::Chebyshev));
+        assert!("euclidean".parse::<Metric>().is_err());
     }
 
     #[test]
     pub fn test_part_1_real() {
-        assert_eq!(347449, part_1(real_data()));
+        assert_eq!(347449, part_1(real_data()).1);
     }
 
     #[test]
     pub fn test_part_2_real() {
-        assert_eq!(98039527, part_2(real_data()));
+        assert_eq!(98039527, part_2(real_data()).1);
     }
 }
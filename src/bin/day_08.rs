@@ -1,6 +1,7 @@
-use std::{path::PathBuf, collections::HashSet};
+use std::{path::PathBuf, str::FromStr, fmt, ops::{BitAnd, BitOr, BitXor}};
 use advent_of_code_2021::iter_lines;
-use structopt::StructOpt;
+use itertools::Itertools;
+use structopt::{clap, StructOpt};
 
 const DATA_FILE: &str = "data/day_08.txt";
 
@@ -10,20 +11,40 @@ struct Opts {
 
     #[structopt(parse(from_os_str), default_value = DATA_FILE)]
     filename: PathBuf,
+
+    /// Print the wire to segment mapping found for each entry
+    #[structopt(long)]
+    wiring: bool,
+
+    /// Display encoding: decimal, hex, 14-segment or 16-segment
+    #[structopt(long, default_value = "decimal")]
+    encoding: Encoding,
+
+    /// Check each entry for corrupted patterns, repairing them where possible
+    #[structopt(long)]
+    validate: bool,
+
+    /// Draw each entry's output as wired and as decoded
+    #[structopt(long)]
+    render: bool,
+
+    /// Decode from only the first N signals of each entry, and find how many are needed
+    #[structopt(long)]
+    observe: Option<usize>,
 }
 
-fn parse_input(lines: impl Iterator<Item = String>) -> impl Iterator<Item = (Vec<String>, Vec<String>)> {
+fn parse_input(lines: impl Iterator<Item = String>) -> impl Iterator<Item = (Vec<SegmentSet>, Vec<SegmentSet>)> {
     lines.into_iter()
         .map(|s| {
             let (l, r) = s.trim().split_once(" | ").unwrap();
-            let split_parts = |x: &str| x.trim().split(" ").map(|s| s.into()).collect::<Vec<String>>();
+            let split_parts = |x: &str| x.trim().split(" ").map(|s| s.parse().unwrap()).collect::<Vec<SegmentSet>>();
             (split_parts(l), split_parts(r))
         })
 }
 
-fn part_1(lines: impl Iterator<Item = String>) -> usize {
-    // want digits 1 (2 segs), 4 (4 segs), 7 (3 segs), 8 (7 segs)
-    let wanted = vec![2 as usize, 4, 3, 7];
+fn part_1(lines: impl Iterator<Item = String>, enc: &Encoding) -> usize {
+    // want the glyphs known by their length alone, digits 1, 4, 7 and 8 in decimal
+    let wanted = enc.unique_lengths();
     parse_input(lines)
         .map(|(_, r)| r)
         .map(|r| r.iter().filter(|x| wanted.contains(&x.len())).count())
@@ -38,82 +59,612 @@ fn part_1(lines: impl Iterator<Item = String>) -> usize {
 //  6: 0 6 9
 //  7: 8
 
-fn in_common(a: &str, b: &str) -> usize {
-    a.chars()
-        .filter(|c| b.contains(*c))
-        .count()
-}
-
-fn remove_if(v: &mut HashSet<String>, p: impl Fn(&&String) -> bool) -> String {
-    let val = v.iter().find(p).unwrap();
-    let res = val.clone();
-    v.remove(&res);
-    res
-}
-
-fn determine_segment_map(segs: &Vec<String>) -> Vec<String> {
-    let mut segs = segs.iter()
-        .map(String::clone)
-        .collect::<HashSet<_>>();
-    
-    // 1 4 7 8 can work out by length
-    let n1 = remove_if(&mut segs, |x| x.len() == 2);
-    let n4 = remove_if(&mut segs, |x| x.len() == 4);
-    let n7 = remove_if(&mut segs, |x| x.len() == 3);
-    let n8 = remove_if(&mut segs, |x| x.len() == 7);
-
-    // 3 has 5 segs and 2 of those are in 1
-    let n3 = remove_if(&mut segs, |x| x.len() == 5 && in_common(x, &n1) == 2);
-    // 2 has 5 segs, 2 in common with 4
-    let n2 = remove_if(&mut segs, |x| x.len() == 5 && in_common(x, &n4) == 2);
-    // 5 has 5 segs, 3 in common with 4
-    let n5 = remove_if(&mut segs, |x| x.len() == 5 && in_common(x, &n4) == 3);
-    // 9 has 6 segs, 5 in common with 3
-    let n9 = remove_if(&mut segs, |x| x.len() == 6 && in_common(x, &n3) == 5);
-    // 0 has 6 segs, 3 in common with 7
-    let n0 = remove_if(&mut segs, |x| x.len() == 6 && in_common(x, &n7) == 3);
-    // 6 is left
-    let n6 = segs.iter().next().unwrap().clone();
-
-    vec![n0, n1, n2, n3, n4, n5, n6, n7, n8, n9]
-}
-
-fn sort_segments(x: &str) -> String {
-    let mut o = x.chars().collect::<Vec<_>>();
-    o.sort();
-    o.into_iter().collect::<String>()
-}
-
-fn process_input_line(l: &Vec<String>, r: &Vec<String>) -> i32 {
-    let l_sorted = l.iter()
-        .map(|x| sort_segments(x))
-        .collect::<Vec<_>>();
-    let r_sorted = r.iter()
-        .map(|x| sort_segments(x))
-        .collect::<Vec<_>>();
-    let mut joined = l_sorted.clone();
-    joined.append(&mut r_sorted.clone());
+// glyph definitions are "symbol:segments", a glyph's value is its position
+//  seven segment displays are lettered
+//    aaaa
+//   b    c
+//   b    c
+//    dddd
+//   e    f
+//   e    f
+//    gggg
+const DECIMAL: &str = "0:abcefg 1:cf 2:acdeg 3:acdfg 4:bcdf 5:abdfg 6:abdefg 7:acf 8:abcdefg 9:abcdfg";
+const HEX: &str = "0:abcefg 1:cf 2:acdeg 3:acdfg 4:bcdf 5:abdfg 6:abdefg 7:acf 8:abcdefg 9:abcdfg \
+    A:abcdef B:bdefg C:abeg D:cdefg E:abdeg F:abde";
+// fourteen segment displays are lettered
+//    aaaaa
+//   fi j kb
+//   f ijk b
+//    gg hh
+//   e lmn c
+//   el m nc
+//    ddddd
+const FOURTEEN: &str = "0:abcdefkl 1:bc 2:abdegh 3:abcdh 4:bcfgh 5:acdfgh 6:acdefgh 7:abc 8:abcdefgh 9:abcdfgh \
+    A:abcefgh B:abcdhjm C:adef D:abcdjm E:adefg F:aefg G:acdefh H:bcefgh I:adjm J:bcde K:efgkn L:def M:bcefik \
+    N:bcefin O:abcdef P:abefgh Q:abcdefn R:abefghn S:acdhi T:ajm U:bcdef V:efkl W:bcefln X:ikln Y:ikm Z:adkl";
+// sixteen segment displays split the top and bottom of the fourteen
+//    aa bb
+//   hk l mc
+//   h klm c
+//    ii jj
+//   g nop d
+//   gn o pd
+//    ff ee
+const SIXTEEN: &str = "0:abcdefghmn 1:bcd 2:abcefgij 3:abcdefj 4:cdhij 5:abdefhij 6:abdefghij 7:abcd 8:abcdefghij \
+    9:abcdefhij A:abcdghij B:abcdefjlo C:abefgh D:abcdeflo E:abefghi F:abghi G:abdefghj H:cdghij I:abeflo \
+    J:cdefg K:ghimp L:fgh M:cdghkm N:cdghkp O:abcdefgh P:abcghij Q:abcdefghp R:abcghijp S:abdefjk T:ablo \
+    U:cdefgh V:ghmn W:cdghnp X:kmnp Y:kmo Z:abefmn";
+
+// bit i is set when segment (or wire) i is lit, where 'a' is bit 0
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+struct SegmentSet(u16);
+
+impl SegmentSet {
+    fn letter(i: usize) -> char {
+        (b'a' + i as u8) as char
+    }
+
+    fn with(self, i: usize) -> SegmentSet {
+        SegmentSet(self.0 | 1 << i)
+    }
+
+    fn contains(self, i: usize) -> bool {
+        self.0 & (1 << i) != 0
+    }
+
+    fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    fn is_subset(self, other: SegmentSet) -> bool {
+        self & other == self
+    }
+
+    fn iter(self) -> impl Iterator<Item = usize> {
+        let mut bits = self.0;
+        std::iter::from_fn(move || {
+            if bits == 0 {
+                return None;
+            }
+            let i = bits.trailing_zeros() as usize;
+            bits &= bits - 1;
+            Some(i)
+        })
+    }
+}
+
+impl BitAnd for SegmentSet {
+    type Output = SegmentSet;
+
+    fn bitand(self, rhs: SegmentSet) -> SegmentSet {
+        SegmentSet(self.0 & rhs.0)
+    }
+}
+
+impl BitOr for SegmentSet {
+    type Output = SegmentSet;
+
+    fn bitor(self, rhs: SegmentSet) -> SegmentSet {
+        SegmentSet(self.0 | rhs.0)
+    }
+}
+
+impl BitXor for SegmentSet {
+    type Output = SegmentSet;
+
+    fn bitxor(self, rhs: SegmentSet) -> SegmentSet {
+        SegmentSet(self.0 ^ rhs.0)
+    }
+}
+
+impl FromStr for SegmentSet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.chars().try_fold(SegmentSet::default(), |set, c| match c {
+            'a'..='p' => Ok(set.with((c as u8 - b'a') as usize)),
+            _ => Err(format!("Bad segment '{}'", c)),
+        })
+    }
+}
+
+impl fmt::Display for SegmentSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.iter().map(SegmentSet::letter).collect::<String>())
+    }
+}
 
-    let seg_map = determine_segment_map(&joined);
+struct Encoding {
+    // number of segments, which also name the wires
+    segments: usize,
+    // symbol shown and segments lit for each glyph
+    glyphs: Vec<(char, SegmentSet)>,
+}
+
+impl Encoding {
+    fn parse(def: &str) -> Result<Encoding, String> {
+        let glyphs = def.split_whitespace()
+            .map(|g| {
+                let (sym, segs) = g.split_once(":").ok_or(format!("Bad glyph '{}'", g))?;
+                let mut sym = sym.chars();
+                match (sym.next(), sym.next()) {
+                    (Some(c), None) => Ok((c, segs.parse()?)),
+                    _ => Err(format!("Bad glyph '{}'", g)),
+                }
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let all = glyphs.iter().fold(SegmentSet::default(), |all, (_, g)| all | *g);
+        let segments = 16 - all.0.leading_zeros() as usize;
+        Ok(Encoding { segments, glyphs })
+    }
 
-    r_sorted.iter()
+    fn decimal() -> Encoding {
+        Encoding::parse(DECIMAL).unwrap()
+    }
+
+    // segment counts lit by just one glyph
+    fn unique_lengths(&self) -> Vec<usize> {
+        self.glyphs.iter()
+            .map(|(_, g)| g.len())
+            .counts()
+            .into_iter()
+            .filter(|(_, n)| *n == 1)
+            .map(|(len, _)| len)
+            .sorted()
+            .collect()
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "decimal" => Encoding::parse(DECIMAL),
+            "hex" => Encoding::parse(HEX),
+            "14-segment" => Encoding::parse(FOURTEEN),
+            "16-segment" => Encoding::parse(SIXTEEN),
+            _ => Err(format!("Unknown encoding '{}'", s)),
+        }
+    }
+}
+
+// wiring[w] is the segment driven by wire w, so wiring[0] is where 'a' goes
+type Wiring = Vec<usize>;
+
+#[derive(Debug, PartialEq)]
+enum Solution {
+    Unique(Wiring),
+    // how many wirings fit, counting no further than MAX_AMBIGUOUS
+    Ambiguous(usize),
+    Inconsistent,
+}
+
+// the wider encodings can leave billions of wirings open, so stop counting here
+const MAX_AMBIGUOUS: usize = 1000;
+
+fn describe_count(wirings: usize) -> String {
+    if wirings >= MAX_AMBIGUOUS {
+        format!("at least {} wirings", wirings)
+    } else {
+        format!("{} wirings", wirings)
+    }
+}
+
+// the segments a wired pattern lights up, for the wires assigned so far
+fn lit_segments(wiring: &[usize], pattern: SegmentSet) -> SegmentSet {
+    pattern.iter()
+        .filter(|w| *w < wiring.len())
+        .fold(SegmentSet::default(), |lit, w| lit.with(wiring[w]))
+}
+
+// true if the pattern could still show the glyph, where `lit` is what the pattern
+// lights up with the wires assigned so far and `assigned` is every segment those
+// wires drive
+fn could_match(pattern: SegmentSet, lit: SegmentSet, glyph: SegmentSet, assigned: SegmentSet) -> bool {
+    pattern.len() == glyph.len() && glyph & assigned == lit
+}
+
+// depth first search over the wire assignments, abandoning a partial wiring
+// as soon as some pattern can't be any glyph
+//  each complete wiring is passed to visit, which returns false to stop the search,
+//  and the search returns false if it was stopped
+fn search_wirings(enc: &Encoding, patterns: &[SegmentSet], wiring: &mut Wiring, visit: &mut impl FnMut(&Wiring) -> bool) -> bool {
+    let assigned = wiring.iter().fold(SegmentSet::default(), |used, seg| used.with(*seg));
+    let fits = |p: &SegmentSet| {
+        let lit = lit_segments(wiring, *p);
+        enc.glyphs.iter().any(|(_, g)| could_match(*p, lit, *g, assigned))
+    };
+    if !patterns.iter().all(fits) {
+        return true;
+    }
+    if wiring.len() == enc.segments {
+        return visit(wiring);
+    }
+    for seg in 0..enc.segments {
+        if !wiring.contains(&seg) {
+            wiring.push(seg);
+            let more = search_wirings(enc, patterns, wiring, visit);
+            wiring.pop();
+            if !more {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+// every wiring that fits the patterns
+fn all_wirings(enc: &Encoding, patterns: &[SegmentSet]) -> Vec<Wiring> {
+    let mut found = vec![];
+    search_wirings(enc, patterns, &mut vec![], &mut |w| {
+        found.push(w.clone());
+        true
+    });
+    found
+}
+
+fn solve_wiring(enc: &Encoding, patterns: &[SegmentSet]) -> Solution {
+    let mut first = None;
+    let mut count = 0;
+    search_wirings(enc, patterns, &mut vec![], &mut |w| {
+        first.get_or_insert_with(|| w.clone());
+        count += 1;
+        count < MAX_AMBIGUOUS
+    });
+    match count {
+        0 => Solution::Inconsistent,
+        1 => Solution::Unique(first.unwrap()),
+        n => Solution::Ambiguous(n),
+    }
+}
+
+fn describe_wiring(wiring: &[usize]) -> String {
+    wiring.iter()
+        .enumerate()
+        .map(|(w, seg)| format!("{}->{}", SegmentSet::letter(w), SegmentSet::letter(*seg)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// the wired pattern for each glyph, in glyph order
+fn determine_segment_map(enc: &Encoding, segs: &[SegmentSet]) -> Vec<SegmentSet> {
+    let wiring = match solve_wiring(enc, segs) {
+        Solution::Unique(w) => w,
+        Solution::Ambiguous(n) => panic!("Ambiguous entry, {} possible", describe_count(n)),
+        Solution::Inconsistent => panic!("Inconsistent entry, no wiring fits"),
+    };
+    enc.glyphs.iter()
+        .map(|(_, g)| {
+            wiring.iter()
+                .enumerate()
+                .filter(|(_, seg)| g.contains(**seg))
+                .fold(SegmentSet::default(), |wires, (w, _)| wires.with(w))
+        })
+        .collect()
+}
+
+// the symbols shown on the output side of an entry
+fn process_input_line(enc: &Encoding, l: &[SegmentSet], r: &[SegmentSet]) -> String {
+    let mut joined = l.to_vec();
+    joined.extend_from_slice(r);
+
+    let seg_map = determine_segment_map(enc, &joined);
+
+    r.iter()
         .map(|x|
-            seg_map.iter().position(|i| i == x).unwrap() as i32
+            enc.glyphs[seg_map.iter().position(|i| i == x).expect("Corrupted entry, try --validate")].0
     )
-    .fold(0, |v, i| v*10 + i)
+    .collect()
+}
+
+#[derive(Debug, PartialEq)]
+struct Analysis {
+    // wirings that fit what's been observed
+    wirings: usize,
+    // each output's glyph, if every one of those wirings agrees on it
+    outputs: Vec<Option<char>>,
+}
+
+fn analyse(enc: &Encoding, observed: &[SegmentSet], outputs: &[SegmentSet]) -> Analysis {
+    let wirings = all_wirings(enc, observed);
+    summarise(enc, wirings.iter(), outputs)
+}
+
+fn summarise<'a>(enc: &Encoding, wirings: impl Iterator<Item = &'a Wiring> + Clone, outputs: &[SegmentSet]) -> Analysis {
+    let outputs = outputs.iter()
+        .map(|o| {
+            let mut lit = wirings.clone().map(|w| lit_segments(w, *o));
+            let first = lit.next()?;
+            if lit.all(|l| l == first) {
+                enc.glyphs.iter().find(|(_, g)| *g == first).map(|(sym, _)| *sym)
+            } else {
+                None
+            }
+        })
+        .collect();
+    Analysis { wirings: wirings.count(), outputs }
+}
+
+// the smallest set of signals for which the analysis passes the test
+//  every wiring is checked against each signal up front, as a bitset over the
+//  wirings, so trying a set of signals is just a matter of and-ing their bitsets
+fn fewest_signals(enc: &Encoding, l: &[SegmentSet], r: &[SegmentSet], test: impl Fn(&Analysis) -> bool) -> Option<Vec<SegmentSet>> {
+    let candidates = all_wirings(enc, &[]);
+    let words = candidates.len().div_ceil(64);
+    let fits = l.iter()
+        .map(|p| {
+            let mut bits = vec![0u64; words];
+            for (i, w) in candidates.iter().enumerate() {
+                if enc.glyphs.iter().any(|(_, g)| lit_segments(w, *p) == *g) {
+                    bits[i / 64] |= 1 << (i % 64);
+                }
+            }
+            bits
+        })
+        .collect::<Vec<_>>();
+    (0..=l.len())
+        .flat_map(|k| (0..l.len()).combinations(k))
+        .find(|observed| {
+            let mut bits = vec![!0u64; words];
+            for p in observed {
+                bits.iter_mut().zip(&fits[*p]).for_each(|(b, f)| *b &= f);
+            }
+            let wirings = candidates.iter()
+                .enumerate()
+                .filter(|(i, _)| bits[i / 64] & (1 << (i % 64)) != 0)
+                .map(|(_, w)| w);
+            test(&summarise(enc, wirings, r))
+        })
+        .map(|observed| observed.iter().map(|p| l[*p]).collect())
+}
+
+// modes that enumerate every wiring, or draw the display, only work for the seven
+// segment encodings: wider ones have 14! or more wirings
+fn seven_segment_only(enc: &Encoding, mode: &str) -> Result<(), String> {
+    if enc.segments == 7 {
+        Ok(())
+    } else {
+        Err(format!("{} only works with seven segment encodings, not {} segments", mode, enc.segments))
+    }
+}
+
+fn usage_error(message: &str) -> ! {
+    clap::Error::with_description(message, clap::ErrorKind::ArgumentConflict).exit()
+}
+
+fn observe_report(enc: &Encoding, n: usize, lines: impl Iterator<Item = String>) -> Vec<String> {
+    let join = |ps: Option<Vec<SegmentSet>>| match ps {
+        Some(ps) => format!("{} ({})", ps.len(), ps.iter().join(" ")),
+        None => "-".to_string(),
+    };
+    parse_input(lines)
+        .map(|(l, r)| {
+            let seen = analyse(enc, &l[..n.min(l.len())], &r);
+            let shown = seen.outputs.iter().map(|o| o.unwrap_or('?')).collect::<String>();
+            let for_wiring = fewest_signals(enc, &l, &r, |a| a.wirings == 1);
+            let for_output = fewest_signals(enc, &l, &r, |a| a.outputs.iter().all(Option::is_some));
+            format!("{} wirings, output {}, wiring needs {}, output needs {}",
+                seen.wirings, shown, join(for_wiring), join(for_output))
+        })
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+enum Check {
+    // decoded output
+    Clean(String),
+    // decoded output, and what was wrong
+    Repaired(String, Vec<String>),
+    // why it couldn't be decoded
+    Rejected(String),
+}
+
+// glyphs nearest to the lit segments, by the number of segments that differ
+fn closest_glyphs(enc: &Encoding, lit: SegmentSet) -> (usize, Vec<char>) {
+    let best = enc.glyphs.iter().map(|(_, g)| (lit ^ *g).len()).min().unwrap();
+    let glyphs = enc.glyphs.iter()
+        .filter(|(_, g)| (lit ^ *g).len() == best)
+        .map(|(sym, _)| *sym)
+        .collect();
+    (best, glyphs)
+}
+
+// find a wiring from the signal patterns, dropping one of them if it has to be
+// corrupted for any wiring to fit
+fn repair_wiring(enc: &Encoding, l: &[SegmentSet], r: &[SegmentSet], notes: &mut Vec<String>) -> Result<Wiring, String> {
+    match solve_wiring(enc, l) {
+        Solution::Unique(w) => return Ok(w),
+        Solution::Ambiguous(n) => return Err(format!("signals fit {}", describe_count(n))),
+        Solution::Inconsistent => {}
+    }
+    let unique = |patterns: &[SegmentSet]| match solve_wiring(enc, patterns) {
+        Solution::Unique(w) => Some(w),
+        _ => None,
+    };
+    let mut fits = (0..l.len())
+        .filter_map(|i| {
+            let mut others = l.iter().enumerate().filter(|(j, _)| *j != i).map(|(_, p)| *p).collect::<Vec<_>>();
+            // without that signal the output may be needed to settle the wiring
+            unique(&others)
+                .or_else(|| {
+                    others.extend_from_slice(r);
+                    unique(&others)
+                })
+                .map(|w| (i, w))
+        })
+        .collect::<Vec<_>>();
+    if fits.len() != 1 {
+        return Err("no wiring fits the signals".to_string());
+    }
+    let (i, w) = fits.remove(0);
+    notes.push(format!("signal '{}' is corrupted, ignored it", l[i]));
+    Ok(w)
+}
+
+fn check_entry(enc: &Encoding, l: &[SegmentSet], r: &[SegmentSet]) -> Check {
+    if let Some(w) = l.iter().chain(r).flat_map(|p| p.iter()).find(|w| *w >= enc.segments) {
+        return Check::Rejected(format!("unknown wire '{}'", SegmentSet::letter(w)));
+    }
+    let mut joined = l.to_vec();
+    joined.extend_from_slice(r);
+    if let Solution::Unique(_) = solve_wiring(enc, &joined) {
+        return Check::Clean(process_input_line(enc, l, r));
+    }
+
+    // something's wrong, so work from the signals alone and repair the output
+    let mut notes = vec![];
+    let wiring = match repair_wiring(enc, l, r, &mut notes) {
+        Ok(w) => w,
+        Err(e) => return Check::Rejected(e),
+    };
+    let mut shown = String::new();
+    for (i, p) in r.iter().enumerate() {
+        let lit = lit_segments(&wiring, *p);
+        let (distance, glyphs) = closest_glyphs(enc, lit);
+        if glyphs.len() > 1 {
+            return Check::Rejected(format!("output {} '{}' could be any of {}", i, p, glyphs.iter().join(",")));
+        }
+        if distance > 0 {
+            let glyph = enc.glyphs.iter().find(|(sym, _)| *sym == glyphs[0]).unwrap().1;
+            let fault = if lit.is_subset(glyph) {
+                "missing segments"
+            } else if glyph.is_subset(lit) {
+                "extra segments"
+            } else {
+                "wrong segments"
+            };
+            notes.push(format!("output {} '{}' has {}, closest to {}", i, p, fault, glyphs[0]));
+        }
+        shown.push(glyphs[0]);
+    }
+    Check::Repaired(shown, notes)
+}
+
+fn validate_report(enc: &Encoding, lines: impl Iterator<Item = String>) -> Vec<String> {
+    let (mut clean, mut repaired, mut rejected) = (0, 0, 0);
+    let mut out = vec![];
+    for (n, (l, r)) in parse_input(lines).enumerate() {
+        match check_entry(enc, &l, &r) {
+            Check::Clean(_) => clean += 1,
+            Check::Repaired(shown, notes) => {
+                repaired += 1;
+                out.push(format!("{}: repaired as {}: {}", n+1, shown, notes.join("; ")));
+            }
+            Check::Rejected(e) => {
+                rejected += 1;
+                out.push(format!("{}: rejected: {}", n+1, e));
+            }
+        }
+    }
+    out.push(format!("clean: {}, repaired: {}, rejected: {}", clean, repaired, rejected));
+    out
+}
+
+// classic three line drawing of the lit segments of a seven segment display
+fn render_segments(lit: &[SegmentSet]) -> Vec<String> {
+    let seg = |p: &SegmentSet, s: usize, c: char| if p.contains(s) { c } else { ' ' };
+    let mut rows = vec![String::new(); 3];
+    for p in lit {
+        rows[0].extend([' ', seg(p, 0, '_'), ' ', ' ']);
+        rows[1].extend([seg(p, 1, '|'), seg(p, 3, '_'), seg(p, 2, '|'), ' ']);
+        rows[2].extend([seg(p, 4, '|'), seg(p, 6, '_'), seg(p, 5, '|'), ' ']);
+    }
+    rows
+}
+
+// the output as it appears with the scrambled wiring, next to how it should look
+//  only seven segment encodings can be drawn
+fn render_entry(enc: &Encoding, l: &[SegmentSet], r: &[SegmentSet]) -> Vec<String> {
+    let wired = render_segments(r);
+    let mut joined = l.to_vec();
+    joined.extend_from_slice(r);
+    let decoded = match solve_wiring(enc, &joined) {
+        Solution::Unique(w) => render_segments(&r.iter().map(|p| lit_segments(&w, *p)).collect::<Vec<_>>()),
+        _ => vec![String::new(), "no unique wiring".to_string(), String::new()],
+    };
+    wired.iter()
+        .zip(["    ", " -> ", "    "])
+        .zip(decoded)
+        .map(|((w, arrow), d)| format!("{}{}{}", w, arrow, d).trim_end().to_string())
+        .collect()
+}
+
+fn render_report(enc: &Encoding, lines: impl Iterator<Item = String>) -> Vec<String> {
+    let entries = parse_input(lines).map(|(l, r)| render_entry(enc, &l, &r));
+    Itertools::intersperse(entries, vec![String::new()])
+        .flatten()
+        .collect()
 }
 
 fn part_2(lines: impl Iterator<Item = String>) -> i32 {
+    let enc = Encoding::decimal();
     parse_input(lines)
-        .map(|(l, r)| process_input_line(&l, &r))
+        .map(|(l, r)| process_input_line(&enc, &l, &r).parse::<i32>().unwrap())
         .sum()
 }
 
+fn decode_report(enc: &Encoding, lines: impl Iterator<Item = String>) -> Vec<String> {
+    parse_input(lines)
+        .map(|(l, r)| process_input_line(enc, &l, &r))
+        .collect()
+}
+
+fn wiring_report(enc: &Encoding, lines: impl Iterator<Item = String>) -> Vec<String> {
+    parse_input(lines)
+        .map(|(mut l, mut r)| {
+            l.append(&mut r);
+            match solve_wiring(enc, &l) {
+                Solution::Unique(w) => describe_wiring(&w),
+                Solution::Ambiguous(n) => format!("ambiguous ({})", describe_count(n)),
+                Solution::Inconsistent => "inconsistent".to_string(),
+            }
+        })
+        .collect()
+}
+
 fn main() {
     let opts = Opts::from_args();
     let data = iter_lines(opts.filename);
+    if opts.wiring {
+        for l in wiring_report(&opts.encoding, data) {
+            println!("{}", l);
+        }
+        return;
+    }
+    if opts.render {
+        if let Err(e) = seven_segment_only(&opts.encoding, "--render") {
+            usage_error(&e);
+        }
+        for l in render_report(&opts.encoding, data) {
+            println!("{}", l);
+        }
+        return;
+    }
+    if let Some(n) = opts.observe {
+        if let Err(e) = seven_segment_only(&opts.encoding, "--observe") {
+            usage_error(&e);
+        }
+        for l in observe_report(&opts.encoding, n, data) {
+            println!("{}", l);
+        }
+        return;
+    }
+    if opts.validate {
+        for l in validate_report(&opts.encoding, data) {
+            println!("{}", l);
+        }
+        return;
+    }
+    if opts.part_no == 2 && opts.encoding.glyphs.len() != 10 {
+        // other displays don't sum as decimal, so show what each entry says
+        for l in decode_report(&opts.encoding, data) {
+            println!("{}", l);
+        }
+        return;
+    }
     match opts.part_no {
-        1 => println!("{}", part_1(data)),
+        1 => println!("{}", part_1(data, &opts.encoding)),
         2 => println!("{}", part_2(data)),
         _ => panic!("Invalid part"),
     }
@@ -146,13 +697,20 @@ mod test {
     pub fn test_parse() {
         let data = parse_input(test_data()).collect::<Vec<_>>();
         assert_eq!(10, data.len());
-        assert_eq!("be", data[0].0[0]);
-        assert_eq!("fgae", data[9].1[0]);
+        assert_eq!(Ok(data[0].0[0]), "be".parse());
+        assert_eq!(Ok(data[9].1[0]), "fgae".parse());
     }
 
     #[test]
     pub fn test_part_1() {
-        assert_eq!(26, part_1(test_data()));
+        assert_eq!(26, part_1(test_data(), &Encoding::decimal()));
+        assert_eq!(vec![2, 3, 4, 7], Encoding::decimal().unique_lengths());
+        // F lights 4 segments, like 4 and C, so only 7, 1 and 8 are known by length
+        let hex = "hex".parse::<Encoding>().unwrap();
+        assert_eq!(vec![2, 3, 7], hex.unique_lengths());
+        let entry = || vec![scrambled_entry(&hex, "gfedcba", "71F8")].into_iter();
+        assert_eq!(3, part_1(entry(), &hex));
+        assert_eq!(4, part_1(entry(), &Encoding::decimal()));
     }
 
     #[test]
@@ -160,9 +718,207 @@ mod test {
         assert_eq!(61229, part_2(test_data()));
     }
 
+    fn patterns(s: &str) -> Vec<SegmentSet> {
+        s.split(' ').map(|p| p.parse().unwrap()).collect()
+    }
+
+    #[test]
+    pub fn test_segment_set() {
+        let a = "cfb".parse::<SegmentSet>().unwrap();
+        let b = "fcdg".parse::<SegmentSet>().unwrap();
+        assert_eq!(SegmentSet(0b0100110), a);
+        assert_eq!("bcf", a.to_string());
+        assert_eq!(3, a.len());
+        assert_eq!("cf", (a & b).to_string());
+        assert_eq!("bcdfg", (a | b).to_string());
+        assert_eq!(3, (a ^ b).len());
+        assert!((a & b).is_subset(a));
+        assert!(!a.is_subset(b));
+        assert!("abz".parse::<SegmentSet>().is_err());
+    }
+
+    #[test]
+    pub fn test_solve_wiring() {
+        let entry = patterns("acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab");
+        let enc = Encoding::decimal();
+        let wiring = vec![2, 5, 6, 0, 1, 3, 4];
+        assert_eq!(Solution::Unique(wiring.clone()), solve_wiring(&enc, &entry));
+        assert_eq!("a->c b->f c->g d->a e->b f->d g->e", describe_wiring(&wiring));
+        let seg_map = determine_segment_map(&enc, &entry);
+        assert_eq!(Ok(seg_map[0]), "cagedb".parse());
+        assert_eq!(Ok(seg_map[5]), "cdfbe".parse());
+
+        // a 1 on its own only pins down which pair of wires drives c and f
+        assert_eq!(Solution::Ambiguous(2 * 120), solve_wiring(&enc, &patterns("ab")));
+        // two different 1s can't both be right
+        assert_eq!(Solution::Inconsistent, solve_wiring(&enc, &patterns("ab cd")));
+        // nor can a pattern with no digit of that length
+        assert_eq!(Solution::Inconsistent, solve_wiring(&enc, &patterns("a")));
+    }
+
+    #[test]
+    pub fn test_analyse() {
+        let enc = Encoding::decimal();
+        let signals = patterns("acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab");
+        let outputs = patterns("cdfeb fcadb cdfeb cdbaf");
+
+        // nothing seen, anything goes
+        assert_eq!(Analysis { wirings: 5040, outputs: vec![None; 4] }, analyse(&enc, &[], &outputs));
+        // everything seen, it's the usual answer
+        let all = analyse(&enc, &signals, &outputs);
+        assert_eq!(1, all.wirings);
+        assert_eq!(vec![Some('5'), Some('3'), Some('5'), Some('3')], all.outputs);
+
+        // the 1, 7 and 4 leave c/f, b/d and e/g each either way round
+        let easy = patterns("ab dab eafb");
+        let some = analyse(&enc, &easy, &outputs);
+        assert_eq!(8, some.wirings);
+        assert_eq!(vec![None; 4], some.outputs);
+
+        let needed = fewest_signals(&enc, &signals, &outputs, |a| a.wirings == 1).unwrap();
+        assert_eq!(1, analyse(&enc, &needed, &outputs).wirings);
+        assert!(needed.len() < signals.len());
+        assert!(needed.iter().copied().combinations(needed.len() - 1).all(|fewer| analyse(&enc, &fewer, &outputs).wirings > 1));
+    }
+
+    #[test]
+    pub fn test_observe_report() {
+        let report = observe_report(&Encoding::decimal(), 10, test_data().take(2));
+        assert_eq!(2, report.len());
+        assert!(report[0].starts_with("1 wirings, output 8394, wiring needs "));
+
+        assert_eq!(Ok(()), seven_segment_only(&"hex".parse().unwrap(), "--observe"));
+        assert_eq!(Err("--observe only works with seven segment encodings, not 14 segments".to_string()),
+            seven_segment_only(&"14-segment".parse().unwrap(), "--observe"));
+    }
+
+    #[test]
+    pub fn test_wiring_report() {
+        let enc = Encoding::decimal();
+        let report = wiring_report(&enc, test_data());
+        assert_eq!(10, report.len());
+        assert!(report.iter().all(|l| l.split(' ').count() == 7));
+        let odd = vec!["ab cd | ab".to_string(), "ab | ab".to_string()];
+        assert_eq!(vec!["inconsistent", "ambiguous (240 wirings)"], wiring_report(&enc, odd.into_iter()));
+    }
+
+    #[test]
+    pub fn test_check_entry() {
+        let enc = Encoding::decimal();
+        let signals = patterns("acedgfb cdfbe gcdfa fbcad dab cefabd cdfgeb eafb cagedb ab");
+        let check = |l: &[SegmentSet], r: &str| check_entry(&enc, l, &patterns(r));
+
+        assert_eq!(Check::Clean("5353".to_string()), check(&signals, "cdfeb fcadb cdfeb cdbaf"));
+        assert_eq!(
+            Check::Repaired("5351".to_string(), vec![
+                "output 2 'bdef' has missing segments, closest to 5".to_string(),
+                "output 3 'abg' has extra segments, closest to 1".to_string(),
+            ]),
+            check(&signals, "cdfeb fcadb dfeb abg"));
+        assert_eq!(
+            Check::Rejected("output 0 'bcdf' could be any of 3,5".to_string()),
+            check(&signals, "cdfb fcadb cdfeb cdbaf"));
+        assert_eq!(Check::Rejected("unknown wire 'h'".to_string()), check(&signals, "cdfeb fcadb cdfeb cdbafh"));
+
+        // a corrupted signal gets left out when the rest pin down the wiring
+        let mut bad_signals = signals.clone();
+        bad_signals[7] = "eaf".parse().unwrap();
+        assert_eq!(
+            Check::Repaired("5353".to_string(), vec!["signal 'aef' is corrupted, ignored it".to_string()]),
+            check(&bad_signals, "cdfeb fcadb cdfeb cdbaf"));
+        // or with help from the output, if they don't
+        bad_signals = signals.clone();
+        bad_signals[4] = "da".parse().unwrap();
+        assert_eq!(
+            Check::Repaired("5753".to_string(), vec!["signal 'ad' is corrupted, ignored it".to_string()]),
+            check(&bad_signals, "cdfeb bad cdfeb cdbaf"));
+    }
+
+    #[test]
+    pub fn test_widely_ambiguous() {
+        // a lone 14-segment 1 leaves billions of wirings, so counting stops early
+        let fourteen = "14-segment".parse::<Encoding>().unwrap();
+        let entry = || vec!["bc | bc".to_string()].into_iter();
+        assert_eq!(Solution::Ambiguous(MAX_AMBIGUOUS), solve_wiring(&fourteen, &patterns("bc")));
+        assert_eq!(vec!["ambiguous (at least 1000 wirings)"], wiring_report(&fourteen, entry()));
+        assert_eq!(vec![
+            "1: rejected: signals fit at least 1000 wirings",
+            "clean: 0, repaired: 0, rejected: 1",
+        ], validate_report(&fourteen, entry()));
+    }
+
+    #[test]
+    pub fn test_validate_report() {
+        let enc = Encoding::decimal();
+        let report = validate_report(&enc, test_data());
+        assert_eq!(vec!["clean: 10, repaired: 0, rejected: 0"], report);
+    }
+
+    #[test]
+    pub fn test_render() {
+        let digits = Encoding::decimal().glyphs.iter().map(|(_, g)| *g).collect::<Vec<_>>();
+        assert_eq!(vec![
+            " _       _   _       _   _   _   _   _  ",
+            "| |   |  _|  _| |_| |_  |_    | |_| |_| ",
+            "|_|   | |_   _|   |  _| |_|   | |_|  _| ",
+        ], render_segments(&digits));
+
+        let entry = parse_input(test_data()).next().unwrap();
+        assert_eq!(vec![
+            " _                   _   _   _",
+            "|_| |_| |_| | |  -> |_|  _| |_| |_|",
+            "|_| | | |_| |_      |_|  _|  _|   |",
+        ], render_entry(&Encoding::decimal(), &entry.0, &entry.1));
+
+        let panel = render_report(&Encoding::decimal(), test_data());
+        assert_eq!(10 * 4 - 1, panel.len());
+        assert_eq!("", panel[3]);
+        assert!(seven_segment_only(&"16-segment".parse().unwrap(), "--render").is_err());
+    }
+
+    // scrambles every glyph of an encoding through the given wiring, as an entry
+    fn scrambled_entry(enc: &Encoding, wiring: &str, shown: &str) -> String {
+        let scramble = |g: SegmentSet| g.iter()
+            .map(|seg| SegmentSet::letter(wiring.find(SegmentSet::letter(seg)).unwrap()))
+            .collect::<String>();
+        let all = enc.glyphs.iter().map(|(_, g)| scramble(*g)).join(" ");
+        let out = shown.chars()
+            .map(|c| scramble(enc.glyphs.iter().find(|(sym, _)| *sym == c).unwrap().1))
+            .join(" ");
+        format!("{} | {}", all, out)
+    }
+
+    #[test]
+    pub fn test_encodings() {
+        for name in ["decimal", "hex", "14-segment", "16-segment"] {
+            let enc = name.parse::<Encoding>().unwrap();
+            // every glyph must be distinguishable
+            assert_eq!(enc.glyphs.len(), enc.glyphs.iter().map(|(_, g)| g).unique().count());
+        }
+        assert_eq!(7, Encoding::decimal().segments);
+        assert_eq!(16, "16-segment".parse::<Encoding>().unwrap().segments);
+        assert!("octal".parse::<Encoding>().is_err());
+        assert!(Encoding::parse("1:cf 23:acdeg").is_err());
+    }
+
+    #[test]
+    pub fn test_decode_encodings() {
+        let hex = "hex".parse::<Encoding>().unwrap();
+        let entry = scrambled_entry(&hex, "gfedcba", "C0DE");
+        assert_eq!(vec!["C0DE"], decode_report(&hex, vec![entry].into_iter()));
+
+        let fourteen = "14-segment".parse::<Encoding>().unwrap();
+        let entry = scrambled_entry(&fourteen, "nmlkjihgfedcba", "HELLO");
+        assert_eq!(vec!["HELLO"], decode_report(&fourteen, vec![entry].into_iter()));
+
+        let sixteen = "16-segment".parse::<Encoding>().unwrap();
+        let entry = scrambled_entry(&sixteen, "bacdefghijklmnpo", "W0RLD");
+        assert_eq!(vec!["W0RLD"], decode_report(&sixteen, vec![entry].into_iter()));
+    }
+
     #[test]
     pub fn test_part_1_real() {
-        assert_eq!(237, part_1(real_data()));
+        assert_eq!(237, part_1(real_data(), &Encoding::decimal()));
     }
 
     #[test]
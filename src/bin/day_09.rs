@@ -1,5 +1,5 @@
-use std::{path::PathBuf, collections::VecDeque};
-use advent_of_code_2021::iter_lines;
+use std::{path::PathBuf, collections::{BTreeMap, VecDeque}, str::FromStr, time::Instant};
+use advent_of_code_2021::{grid, iter_lines};
 use structopt::StructOpt;
 
 const DATA_FILE: &str = "data/day_09.txt";
@@ -10,38 +10,149 @@ struct Opts {
 
     #[structopt(parse(from_os_str), default_value = DATA_FILE)]
     filename: PathBuf,
+
+    /// Cells adjacent to each cell: von-neumann (4), moore (8) or hex (6)
+    #[structopt(long, default_value = "von-neumann")]
+    neighbourhood: Neighbourhood,
+
+    /// Merge flat minima and drain every cell by steepest descent, rather than walling basins at 9
+    #[structopt(long)]
+    watershed: bool,
+
+    /// Height at and above which cells are walls between basins
+    #[structopt(long, default_value = "9")]
+    wall: u32,
+
+    /// Print the heightmap with each basin in its own colour
+    #[structopt(long)]
+    render: bool,
+
+    /// Write the basin map to this file as a PPM image
+    #[structopt(long, parse(from_os_str))]
+    ppm: Option<PathBuf>,
+
+    /// Pixels per cell in the PPM image, at least 1
+    #[structopt(long, default_value = "8", parse(try_from_str = parse_positive))]
+    scale: usize,
+
+    /// Raise water over the heightmap, reporting the flooded area at each level and where basins merge
+    #[structopt(long)]
+    flood: bool,
+
+    /// Report the catchment, longest flow and passes of each basin when every cell drains to its lowest neighbour
+    #[structopt(long)]
+    drainage: bool,
+
+    /// Write the drainage graph to this file in Graphviz DOT format
+    #[structopt(long, parse(from_os_str))]
+    dot: Option<PathBuf>,
+
+    /// Find the cheapest path from the top left to the bottom right corner: entered (pay the height of each cell entered) or climb[:N] (unit steps rising at most N, default 1)
+    #[structopt(long)]
+    path: Option<PathCost>,
+
+    /// List every basin between the walls with its size and low points
+    #[structopt(long)]
+    basins: bool,
+
+    /// Time the flood fill against union-find labelling of the basins
+    #[structopt(long)]
+    bench: bool,
+
+    /// Read a voxel grid of layers separated by blank lines, with voxels adjacent across faces (6) or cubes (26)
+    #[structopt(long)]
+    voxels: Option<Neighbourhood3>,
 }
 
 type Grid = Vec<Vec<u32>>;
 
+// rows are either runs of single digits, or heights separated by spaces or commas
 fn parse_input(lines: impl Iterator<Item = String>) -> Grid {
     lines.into_iter()
         .map(|s| {
-            s.trim().bytes().map(|i| (i - b'0') as u32 ).collect()
+            let s = s.trim();
+            if s.contains(|c: char| c == ',' || c.is_whitespace()) {
+                s.split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|h| !h.is_empty())
+                    .map(|h| h.parse().expect("Invalid height"))
+                    .collect()
+            } else {
+                s.bytes().map(|i| (i - b'0') as u32 ).collect()
+            }
         })
         .collect()
 }
 
-fn is_low_point(g: &Grid, r: usize, c: usize) -> Option<u32> {
-    const INV: u32 = 999;
+// a count that must be at least 1
+fn parse_positive(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) => Err("Must be at least 1".to_string()),
+        Ok(n) => Ok(n),
+        Err(_) => Err(format!("Invalid count '{}'", s)),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Neighbourhood {
+    // the 4 orthogonal cells
+    VonNeumann,
+    // the 8 orthogonal and diagonal cells
+    Moore,
+    // the 6 cells around a hexagon, with odd rows shifted half a cell right
+    Hex,
+}
+
+impl FromStr for Neighbourhood {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "von-neumann" => Ok(Neighbourhood::VonNeumann),
+            "moore" => Ok(Neighbourhood::Moore),
+            "hex" => Ok(Neighbourhood::Hex),
+            _ => Err(format!("Unknown neighbourhood '{}'", s)),
+        }
+    }
+}
+
+impl Neighbourhood {
+    // (row, col) steps to the neighbours of a cell in row r
+    fn offsets(self, r: usize) -> &'static [(i32, i32)] {
+        match self {
+            Neighbourhood::VonNeumann => &[(-1, 0), (1, 0), (0, -1), (0, 1)],
+            Neighbourhood::Moore => &[(-1, -1), (-1, 0), (-1, 1), (0, -1), (0, 1), (1, -1), (1, 0), (1, 1)],
+            Neighbourhood::Hex if r.is_multiple_of(2) => &[(-1, -1), (-1, 0), (0, -1), (0, 1), (1, -1), (1, 0)],
+            Neighbourhood::Hex => &[(-1, 0), (-1, 1), (0, -1), (0, 1), (1, 0), (1, 1)],
+        }
+    }
+
+    fn neighbours(self, g: &Grid, r: usize, c: usize) -> impl Iterator<Item = (usize, usize)> {
+        let (rows, cols) = (g.len() as i32, g[0].len() as i32);
+        self.offsets(r).iter()
+            .map(move |(dr, dc)| (r as i32 + dr, c as i32 + dc))
+            .filter(move |(r, c)| *r >= 0 && *r < rows && *c >= 0 && *c < cols)
+            .map(|(r, c)| (r as usize, c as usize))
+    }
+}
+
+fn is_low_point(g: &Grid, n: Neighbourhood, r: usize, c: usize) -> Option<u32> {
     let h = g[r][c];
-    let u = if r > 0 { g[r-1][c] } else { INV };
-    let d = if r < g.len()-1 { g[r+1][c] } else { INV };
-    let l = if c > 0 { g[r][c-1] } else { INV };
-    let r = if c < g[0].len()-1 { g[r][c+1] } else { INV };
-    if h < u && h < d && h < l && h < r {
+    if n.neighbours(g, r, c).all(|(nr, nc)| h < g[nr][nc]) {
         Some(h)
     } else {
         None
     }
 }
 
-fn part_1(lines: impl Iterator<Item = String>) -> u32 {
-    let grid = parse_input(lines);
+fn part_1(lines: impl Iterator<Item = String>, n: Neighbourhood) -> u32 {
+    risk_level(&parse_input(lines), n)
+}
+
+fn risk_level(grid: &Grid, n: Neighbourhood) -> u32 {
     let mut sum = 0;
     for r in 0..grid.len() {
         for c in 0..grid[r].len() {
-            if let Some(h) = is_low_point(&grid, r, c) {
+            if let Some(h) = is_low_point(grid, n, r, c) {
                 let v = h + 1;
                 sum += v;
             }
@@ -50,75 +161,824 @@ fn part_1(lines: impl Iterator<Item = String>) -> u32 {
     sum
 }
 
-fn part_2(lines: impl Iterator<Item = String>) -> i32 {
-    let mut grid = parse_input(lines);
+fn part_2(lines: impl Iterator<Item = String>, n: Neighbourhood, wall: u32) -> usize {
+    basin_product(&parse_input(lines), n, wall)
+}
 
-    // find the low points
-    let mut lows= vec![];
-    for r in 0..grid.len() {
-        for c in 0..grid[r].len() {
-            if let Some(_) = is_low_point(&grid, r, c) {
-                lows.push((r, c));
-            }
+// basin of every cell below the wall height, found by flooding out from the low points
+struct Basins {
+    labels: Vec<Vec<Option<usize>>>,
+    lows: Vec<(usize, usize)>,
+}
+
+impl Basins {
+    // cells in each basin
+    fn sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![0; self.lows.len()];
+        for b in self.labels.iter().flatten().flatten() {
+            sizes[*b] += 1;
         }
+        sizes
     }
+}
 
-    // mark the basins with numbers >= 10
+// basin of every cell, by its index, and the low point of each basin
+type Labelled<P> = (Vec<Option<usize>>, Vec<P>);
+
+// flood out from the low points below the wall until the walls or another basin
+// are reached, in 2-D or 3-D: index numbers each of the cells from 0
+fn flood_basins<P, I>(
+    cells: impl Iterator<Item = P> + Clone,
+    index: impl Fn(P) -> usize,
+    height: impl Fn(P) -> u32,
+    neighbours: impl Fn(P) -> I,
+    wall: u32,
+) -> Labelled<P>
+where
+    P: Copy,
+    I: IntoIterator<Item = P>,
+{
+    // find the low points
+    let lows: Vec<_> = cells.clone()
+        .filter(|p| height(*p) < wall && neighbours(*p).into_iter().all(|q| height(*p) < height(q)))
+        .collect();
 
     // work queue says which cells to examine next
     //  start from the low points
-    let mut work_queue = VecDeque::new();
-    for (i, p) in lows.iter().enumerate() {
-        let v = 10 + i as u32;
-        work_queue.push_back((*p, v));
-    }
+    let mut labels = vec![None; cells.count()];
+    let mut work_queue: VecDeque<_> = lows.iter().enumerate().map(|(i, p)| (*p, i)).collect();
 
     // process the queue
-    //   if the cell is not already marked, or not high (9), then mark it and
+    //   if the cell is not already labelled, or a wall, then label it and
     //   queue its neighbours for examination
-    while !work_queue.is_empty() {
-        let ((r, c), t) = work_queue.pop_front().unwrap();
-        let v = grid[r][c];
-        if v >= 9 {
+    while let Some((p, b)) = work_queue.pop_front() {
+        if labels[index(p)].is_some() || height(p) >= wall {
             continue;
         }
-        grid[r][c] = t;
+        labels[index(p)] = Some(b);
         // enqueue neighbours
-        if c > 0 {
-            work_queue.push_back(((r, c-1), t));
+        for q in neighbours(p) {
+            work_queue.push_back((q, b));
+        }
+    }
+
+    (labels, lows)
+}
+
+fn label_basins(grid: &Grid, n: Neighbourhood, wall: u32) -> Basins {
+    let cols = grid[0].len();
+    let cells = (0..grid.len()).flat_map(|r| (0..cols).map(move |c| (r, c)));
+    let (labels, lows) = flood_basins(
+        cells,
+        |(r, c)| r * cols + c,
+        |(r, c)| grid[r][c],
+        |(r, c)| n.neighbours(grid, r, c),
+        wall,
+    );
+    Basins { labels: labels.chunks(cols).map(<[_]>::to_vec).collect(), lows }
+}
+
+fn basin_product(grid: &Grid, n: Neighbourhood, wall: u32) -> usize {
+    let mut basin_sizes = label_basins(grid, n, wall).sizes();
+    basin_sizes.sort();
+    basin_sizes.iter().rev().take(3).product()
+}
+
+struct Watershed {
+    // basin of every cell
+    labels: Vec<Vec<usize>>,
+    // first cell and height of each basin's minimum
+    minima: Vec<((usize, usize), u32)>,
+}
+
+impl Watershed {
+    // cells in each basin
+    fn sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![0; self.minima.len()];
+        for b in self.labels.iter().flatten() {
+            sizes[*b] += 1;
+        }
+        sizes
+    }
+}
+
+// plateau id of every cell, and the cells of each plateau
+type Plateaus = (Vec<Vec<usize>>, Vec<Vec<(usize, usize)>>);
+
+// split the grid into connected regions of equal height
+fn plateaus(g: &Grid, n: Neighbourhood) -> Plateaus {
+    let mut ids = vec![vec![usize::MAX; g[0].len()]; g.len()];
+    let mut cells = vec![];
+    for r in 0..g.len() {
+        for c in 0..g[r].len() {
+            if ids[r][c] != usize::MAX {
+                continue;
+            }
+            let id = cells.len();
+            let mut plateau = vec![];
+            let mut work_queue = VecDeque::from(vec![(r, c)]);
+            ids[r][c] = id;
+            while let Some((r, c)) = work_queue.pop_front() {
+                plateau.push((r, c));
+                for (nr, nc) in n.neighbours(g, r, c) {
+                    if ids[nr][nc] == usize::MAX && g[nr][nc] == g[r][c] {
+                        ids[nr][nc] = id;
+                        work_queue.push_back((nr, nc));
+                    }
+                }
+            }
+            cells.push(plateau);
+        }
+    }
+    (ids, cells)
+}
+
+// where each cell drains: to its lowest strictly lower neighbour, or on a flat
+// stretch towards the nearest cell that has somewhere lower to go. A plateau
+// with nowhere lower to go is a minimum, and drains towards its first cell,
+// which is left as the only sink
+type Flow = (Vec<Vec<Option<(usize, usize)>>>, Vec<((usize, usize), u32)>);
+
+fn flow(g: &Grid, n: Neighbourhood) -> Flow {
+    let (ids, cells) = plateaus(g, n);
+    let lower = |(r, c): (usize, usize)| n.neighbours(g, r, c)
+        .filter(|(nr, nc)| g[*nr][*nc] < g[r][c])
+        .min_by_key(|(nr, nc)| g[*nr][*nc]);
+
+    let mut flow = vec![vec![None; g[0].len()]; g.len()];
+    let mut minima = vec![];
+    for (id, plateau) in cells.iter().enumerate() {
+        let mut work_queue = VecDeque::new();
+        for p in plateau {
+            if let Some(to) = lower(*p) {
+                flow[p.0][p.1] = Some(to);
+                work_queue.push_back(*p);
+            }
+        }
+        let mut sink = None;
+        if work_queue.is_empty() {
+            let (r, c) = plateau[0];
+            minima.push(((r, c), g[r][c]));
+            sink = Some((r, c));
+            work_queue.push_back((r, c));
+        }
+        while let Some((r, c)) = work_queue.pop_front() {
+            for (nr, nc) in n.neighbours(g, r, c) {
+                if ids[nr][nc] == id && flow[nr][nc].is_none() && Some((nr, nc)) != sink {
+                    flow[nr][nc] = Some((r, c));
+                    work_queue.push_back((nr, nc));
+                }
+            }
+        }
+    }
+    // sinks in the same order as the cells
+    minima.sort();
+    (flow, minima)
+}
+
+// every cell belongs to the basin of the minimum it drains to
+fn watershed(g: &Grid, n: Neighbourhood) -> Watershed {
+    let d = drainage(g, n);
+    Watershed { labels: d.basin, minima: d.sinks }
+}
+
+impl Watershed {
+    // the same labelling as a flood fill with no walls
+    fn basins(self) -> Basins {
+        Basins {
+            labels: self.labels.into_iter().map(|r| r.into_iter().map(Some).collect()).collect(),
+            lows: self.minima.into_iter().map(|(p, _)| p).collect(),
+        }
+    }
+}
+
+type Rgb = (u8, u8, u8);
+
+const WALL_COLOUR: Rgb = (96, 96, 96);
+const LOW_COLOUR: Rgb = (255, 255, 255);
+
+// spread the hues around the colour wheel by the golden angle so neighbouring
+// basin numbers look nothing alike, and darken higher cells
+fn basin_colour(b: usize, h: u32, max_h: u32) -> Rgb {
+    let hue = (b as f64 * 137.508) % 360.0 / 60.0;
+    let v = 1.0 - 0.6 * h as f64 / max_h.max(1) as f64;
+    let x = 1.0 - (hue % 2.0 - 1.0).abs();
+    let (r, g, b) = match hue as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+    let scale = |c: f64| (255.0 * v * (0.3 + 0.7 * c)) as u8;
+    (scale(r), scale(g), scale(b))
+}
+
+fn colour_cells(grid: &Grid, basins: &Basins) -> Vec<Vec<Rgb>> {
+    let max_h = grid.iter().flatten().copied().max().unwrap_or(0);
+    let mut colours: Vec<Vec<Rgb>> = grid.iter().zip(basins.labels.iter())
+        .map(|(hs, bs)| hs.iter().zip(bs.iter())
+            .map(|(h, b)| match b {
+                Some(b) => basin_colour(*b, *h, max_h),
+                None => WALL_COLOUR,
+            })
+            .collect())
+        .collect();
+    for (r, c) in &basins.lows {
+        colours[*r][*c] = LOW_COLOUR;
+    }
+    colours
+}
+
+// one line per row, heights on a 24-bit background
+fn render_ansi(grid: &Grid, colours: &[Vec<Rgb>]) -> Vec<String> {
+    let width = grid.iter().flatten().max().map_or(1, |h| h.to_string().len());
+    grid.iter().zip(colours.iter())
+        .map(|(hs, cs)| {
+            let mut line = String::new();
+            for (h, (r, g, b)) in hs.iter().zip(cs.iter()) {
+                line += &format!("\x1b[30;48;2;{};{};{}m{:>w$}", r, g, b, h, w = width);
+            }
+            line + "\x1b[0m"
+        })
+        .collect()
+}
+
+// binary PPM, each cell a scale x scale square
+fn render_ppm(colours: &[Vec<Rgb>], scale: usize) -> Vec<u8> {
+    let (rows, cols) = (colours.len(), colours[0].len());
+    let mut image = format!("P6\n{} {}\n255\n", cols * scale, rows * scale).into_bytes();
+    for row in colours {
+        for _ in 0..scale {
+            for (r, g, b) in row {
+                for _ in 0..scale {
+                    image.extend_from_slice(&[*r, *g, *b]);
+                }
+            }
+        }
+    }
+    image
+}
+
+// disjoint sets of cells, indexed by r * cols + c
+struct UnionFind {
+    parent: Vec<usize>,
+    size: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(n: usize) -> Self {
+        UnionFind { parent: (0..n).collect(), size: vec![1; n] }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            // path halving
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    // joins the sets, keeping root a as the root of the result
+    fn union_into(&mut self, a: usize, b: usize) {
+        self.parent[b] = a;
+        self.size[a] += self.size[b];
+    }
+}
+
+// a connected region below the wall height, and the low points inside it
+#[derive(Debug, PartialEq)]
+struct Basin {
+    lows: Vec<(usize, usize)>,
+    size: usize,
+}
+
+// join each cell to the neighbours already visited, so every region is found
+// whole without a work queue, then make a second pass over the grid to number
+// the basins and collect their low points
+fn union_basins(grid: &Grid, n: Neighbourhood, wall: u32) -> Vec<Basin> {
+    let cols = grid[0].len();
+    let mut sets = UnionFind::new(grid.len() * cols);
+    for r in 0..grid.len() {
+        for c in 0..cols {
+            if grid[r][c] >= wall {
+                continue;
+            }
+            for (nr, nc) in n.neighbours(grid, r, c) {
+                if (nr, nc) < (r, c) && grid[nr][nc] < wall {
+                    let (a, b) = (sets.find(nr * cols + nc), sets.find(r * cols + c));
+                    if a != b {
+                        sets.union_into(a, b);
+                    }
+                }
+            }
+        }
+    }
+
+    // basins in the order their first cells appear
+    let mut index = vec![usize::MAX; grid.len() * cols];
+    let mut basins = vec![];
+    for r in 0..grid.len() {
+        for c in 0..cols {
+            if grid[r][c] >= wall {
+                continue;
+            }
+            let root = sets.find(r * cols + c);
+            if index[root] == usize::MAX {
+                index[root] = basins.len();
+                basins.push(Basin { lows: vec![], size: sets.size[root] });
+            }
+            if is_low_point(grid, n, r, c).is_some() {
+                basins[index[root]].lows.push((r, c));
+            }
+        }
+    }
+    basins
+}
+
+fn basins_report(basins: &[Basin]) -> Vec<String> {
+    basins.iter()
+        .map(|b| {
+            let lows = b.lows.iter().map(|(r, c)| format!("({},{})", r, c)).collect::<Vec<_>>().join(", ");
+            format!("{} cells, low points {}", b.size, lows)
+        })
+        .collect()
+}
+
+// average time per run of each way of finding the basins
+fn bench(grid: &Grid, n: Neighbourhood, wall: u32, runs: u32) -> Vec<String> {
+    let start = Instant::now();
+    for _ in 0..runs {
+        label_basins(grid, n, wall);
+    }
+    let bfs = start.elapsed() / runs;
+    let start = Instant::now();
+    for _ in 0..runs {
+        union_basins(grid, n, wall);
+    }
+    let union_find = start.elapsed() / runs;
+    vec![format!("flood fill: {:?}", bfs), format!("union-find: {:?}", union_find)]
+}
+
+#[derive(Debug, PartialEq)]
+struct Level {
+    height: u32,
+    flooded: usize,
+    basins: usize,
+}
+
+// when the water reaches `level` the basin around `absorbed` spills into the
+// one around `survivor`, which has the deeper (or equally deep, earlier) minimum
+#[derive(Debug, PartialEq)]
+struct Merge {
+    level: u32,
+    survivor: ((usize, usize), u32),
+    absorbed: ((usize, usize), u32),
+}
+
+struct Flood {
+    levels: Vec<Level>,
+    merges: Vec<Merge>,
+    // minima of the basins left once everything is under water
+    survivors: Vec<((usize, usize), u32)>,
+}
+
+impl Flood {
+    // (birth, death) of every basin: the height of its minimum and the level
+    // it merges into an older basin, or None for basins that never do
+    fn persistence(&self) -> Vec<(u32, Option<u32>)> {
+        self.merges.iter()
+            .map(|m| (m.absorbed.1, Some(m.level)))
+            .chain(self.survivors.iter().map(|(_, h)| (*h, None)))
+            .collect()
+    }
+}
+
+fn flood(g: &Grid, n: Neighbourhood) -> Flood {
+    let cols = g[0].len();
+    let mut cells: Vec<_> = (0..g.len()).flat_map(|r| (0..cols).map(move |c| (r, c))).collect();
+    cells.sort_by_key(|(r, c)| g[*r][*c]);
+
+    let mut sets = UnionFind::new(g.len() * cols);
+    // the minimum of each set, by its root
+    let mut minimum = vec![((0, 0), 0); g.len() * cols];
+    let mut flooded = vec![vec![false; cols]; g.len()];
+    let mut basins = 0;
+    let mut levels = vec![];
+    let mut merges = vec![];
+
+    let mut i = 0;
+    while i < cells.len() {
+        let height = g[cells[i].0][cells[i].1];
+        // flood every cell at this height, joining it to the water around it
+        while i < cells.len() && g[cells[i].0][cells[i].1] == height {
+            let (r, c) = cells[i];
+            flooded[r][c] = true;
+            minimum[r * cols + c] = ((r, c), height);
+            basins += 1;
+            for (nr, nc) in n.neighbours(g, r, c) {
+                if !flooded[nr][nc] {
+                    continue;
+                }
+                let (a, b) = (sets.find(r * cols + c), sets.find(nr * cols + nc));
+                if a == b {
+                    continue;
+                }
+                // the elder basin survives
+                let (a, b) = if (minimum[a].1, minimum[a].0) <= (minimum[b].1, minimum[b].0) { (a, b) } else { (b, a) };
+                // a cell rising to join water already there is not a merge
+                if minimum[b].1 < height {
+                    merges.push(Merge { level: height, survivor: minimum[a], absorbed: minimum[b] });
+                }
+                sets.union_into(a, b);
+                basins -= 1;
+            }
+            i += 1;
+        }
+        levels.push(Level { height, flooded: i, basins });
+    }
+    let mut survivors: Vec<_> = (0..g.len() * cols).map(|i| minimum[sets.find(i)]).collect();
+    survivors.sort();
+    survivors.dedup();
+    Flood { levels, merges, survivors }
+}
+
+fn flood_report(f: &Flood) -> Vec<String> {
+    let mut lines = vec![];
+    let mut merges = f.merges.iter().peekable();
+    for l in &f.levels {
+        lines.push(format!("level {}: {} cells flooded, {} basins", l.height, l.flooded, l.basins));
+        while let Some(m) = merges.next_if(|m| m.level == l.height) {
+            let ((sr, sc), sh) = m.survivor;
+            let ((ar, ac), ah) = m.absorbed;
+            lines.push(format!("  basin at ({},{}) height {} absorbs basin at ({},{}) height {}", sr, sc, sh, ar, ac, ah));
         }
-        if c < grid[r].len()-1 {
-            work_queue.push_back(((r, c+1), t));
+    }
+    lines.push("persistence:".to_string());
+    for (birth, death) in f.persistence() {
+        match death {
+            Some(d) => lines.push(format!("  {} -> {} ({})", birth, d, d - birth)),
+            None => lines.push(format!("  {} -> never", birth)),
         }
-        if r > 0 {
-            work_queue.push_back(((r-1, c), t));
+    }
+    lines
+}
+
+// every cell drains as described by flow, down to the first cell of a minimum
+struct Drainage {
+    downstream: Vec<Vec<Option<(usize, usize)>>>,
+    sinks: Vec<((usize, usize), u32)>,
+    // the sink each cell ends up in, and how many steps it takes to get there
+    basin: Vec<Vec<usize>>,
+    steps: Vec<Vec<usize>>,
+    // lowest height water must reach to spill between each pair of adjacent basins
+    passes: BTreeMap<(usize, usize), u32>,
+}
+
+impl Drainage {
+    // cells draining into each sink
+    fn catchments(&self) -> Vec<usize> {
+        let mut sizes = vec![0; self.sinks.len()];
+        for b in self.basin.iter().flatten() {
+            sizes[*b] += 1;
         }
-        if r < grid.len()-1 {
-            work_queue.push_back(((r+1, c), t));
+        sizes
+    }
+
+    // start and length of the longest path down into each sink
+    fn longest_flows(&self) -> Vec<((usize, usize), usize)> {
+        let mut longest: Vec<_> = self.sinks.iter().map(|(p, _)| (*p, 0)).collect();
+        for (r, row) in self.steps.iter().enumerate() {
+            for (c, s) in row.iter().enumerate() {
+                let b = self.basin[r][c];
+                if *s > longest[b].1 {
+                    longest[b] = ((r, c), *s);
+                }
+            }
+        }
+        longest
+    }
+}
+
+fn drainage(g: &Grid, n: Neighbourhood) -> Drainage {
+    let (rows, cols) = (g.len(), g[0].len());
+    let (downstream, sinks) = flow(g, n);
+    let mut basin = vec![vec![usize::MAX; cols]; rows];
+    for (b, (p, _)) in sinks.iter().enumerate() {
+        basin[p.0][p.1] = b;
+    }
+
+    // follow each cell down, filling in the path on the way back
+    let mut steps = vec![vec![usize::MAX; cols]; rows];
+    for (p, _) in &sinks {
+        steps[p.0][p.1] = 0;
+    }
+    for r in 0..rows {
+        for c in 0..cols {
+            let mut path = vec![];
+            let mut p = (r, c);
+            while steps[p.0][p.1] == usize::MAX {
+                path.push(p);
+                p = downstream[p.0][p.1].unwrap();
+            }
+            let (b, mut s) = (basin[p.0][p.1], steps[p.0][p.1]);
+            for (r, c) in path.into_iter().rev() {
+                s += 1;
+                basin[r][c] = b;
+                steps[r][c] = s;
+            }
         }
     }
 
-    // build the histogram of the basin IDs
-    let mut basin_sizes = vec![0; lows.len()];
-    for r in grid {
-        for v in r {
-            if v > 9 {
-                let b = v - 10;
-                basin_sizes[b as usize] += 1;
+    let mut passes = BTreeMap::new();
+    for r in 0..rows {
+        for c in 0..cols {
+            for (nr, nc) in n.neighbours(g, r, c) {
+                let (a, b) = (basin[r][c], basin[nr][nc]);
+                if a < b {
+                    let h = g[r][c].max(g[nr][nc]);
+                    let pass = passes.entry((a, b)).or_insert(h);
+                    *pass = h.min(*pass);
+                }
             }
         }
     }
 
+    Drainage { downstream, sinks, basin, steps, passes }
+}
+
+fn drainage_report(d: &Drainage) -> Vec<String> {
+    let mut lines = vec![];
+    for (b, ((((r, c), h), size), ((fr, fc), len))) in d.sinks.iter()
+        .zip(d.catchments())
+        .zip(d.longest_flows())
+        .enumerate()
+    {
+        lines.push(format!("basin {} at ({},{}) height {}: {} cells, longest flow {} steps from ({},{})",
+            b, r, c, h, size, len, fr, fc));
+    }
+    for ((a, b), h) in &d.passes {
+        lines.push(format!("pass between basins {} and {} at height {}", a, b, h));
+    }
+    lines
+}
+
+// cells clustered by basin with an edge to where they drain, and dashed
+// edges between sinks labelled with the pass height
+fn drainage_dot(g: &Grid, d: &Drainage) -> String {
+    let mut dot = String::from("digraph drainage {\n");
+    for (b, ((r, c), _)) in d.sinks.iter().enumerate() {
+        dot += &format!("  subgraph cluster_{} {{\n    label=\"basin {}\";\n", b, b);
+        for (cr, row) in d.basin.iter().enumerate() {
+            for (cc, cb) in row.iter().enumerate() {
+                if *cb == b {
+                    let shape = if (cr, cc) == (*r, *c) { ", shape=doublecircle" } else { "" };
+                    dot += &format!("    c{}_{} [label=\"{}\"{}];\n", cr, cc, g[cr][cc], shape);
+                }
+            }
+        }
+        dot += "  }\n";
+    }
+    for (r, row) in d.downstream.iter().enumerate() {
+        for (c, to) in row.iter().enumerate() {
+            if let Some((tr, tc)) = to {
+                dot += &format!("  c{}_{} -> c{}_{};\n", r, c, tr, tc);
+            }
+        }
+    }
+    for ((a, b), h) in &d.passes {
+        let ((ar, ac), _) = d.sinks[*a];
+        let ((br, bc), _) = d.sinks[*b];
+        dot += &format!("  c{}_{} -> c{}_{} [dir=none, style=dashed, label=\"{}\"];\n", ar, ac, br, bc, h);
+    }
+    dot + "}\n"
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PathCost {
+    Entered,
+    Climb(u32),
+}
+
+impl FromStr for PathCost {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "entered" => Ok(PathCost::Entered),
+            None if s == "climb" => Ok(PathCost::Climb(1)),
+            Some(("climb", n)) => n.parse().map(PathCost::Climb).map_err(|_| format!("Invalid climb '{}'", n)),
+            _ => Err(format!("Unknown path cost '{}'", s)),
+        }
+    }
+}
+
+// cheapest path between opposite corners, guided by the fewest steps it could take
+fn corner_path(g: &Grid, n: Neighbourhood, cost: PathCost) -> Option<(u64, Vec<grid::Pos>)> {
+    let goal = (g.len() - 1, g[0].len() - 1);
+    let neighbours = |(r, c): grid::Pos| n.neighbours(g, r, c);
+    // diagonal steps can cover two orthogonal ones
+    let min_steps = |p| match n {
+        Neighbourhood::VonNeumann => grid::manhattan(goal)(p),
+        _ => p.0.abs_diff(goal.0).max(p.1.abs_diff(goal.1)) as u64,
+    };
+    match cost {
+        PathCost::Entered => {
+            let cheapest = g.iter().flatten().copied().min().unwrap_or(0) as u64;
+            grid::astar((0, 0), goal, neighbours, grid::entered_height(g), |p| min_steps(p) * cheapest)
+        }
+        PathCost::Climb(max) => grid::astar((0, 0), goal, neighbours, grid::limited_climb(g, max), min_steps),
+    }
+}
+
+fn watershed_risk(ws: &Watershed) -> u32 {
+    ws.minima.iter().map(|(_, h)| h + 1).sum()
+}
+
+fn watershed_product(ws: &Watershed) -> usize {
+    let mut sizes = ws.sizes();
+    sizes.sort();
+    sizes.iter().rev().take(3).product()
+}
+
+// layers of grids, indexed [z][r][c]
+type Volume = Vec<Grid>;
+
+type Voxel = (usize, usize, usize);
+
+fn parse_volume(lines: impl Iterator<Item = String>) -> Volume {
+    let mut volume = vec![];
+    let mut layer = vec![];
+    for l in lines {
+        if l.trim().is_empty() {
+            if !layer.is_empty() {
+                volume.push(parse_input(layer.drain(..)));
+            }
+        } else {
+            layer.push(l);
+        }
+    }
+    if !layer.is_empty() {
+        volume.push(parse_input(layer.into_iter()));
+    }
+    volume
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Neighbourhood3 {
+    // the 6 voxels sharing a face
+    Faces,
+    // the 26 voxels sharing a face, edge or corner
+    Cube,
+}
+
+impl FromStr for Neighbourhood3 {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "6" | "faces" => Ok(Neighbourhood3::Faces),
+            "26" | "cube" => Ok(Neighbourhood3::Cube),
+            _ => Err(format!("Unknown neighbourhood '{}'", s)),
+        }
+    }
+}
+
+impl Neighbourhood3 {
+    fn neighbours(self, v: &Volume, (z, r, c): Voxel) -> impl Iterator<Item = Voxel> {
+        let (layers, rows, cols) = (v.len() as i32, v[0].len() as i32, v[0][0].len() as i32);
+        let cube = (-1..=1).flat_map(|dz| (-1..=1).flat_map(move |dr| (-1..=1).map(move |dc| (dz, dr, dc))));
+        cube.filter(move |(dz, dr, dc): &(i32, i32, i32)| match self {
+                Neighbourhood3::Faces => dz.abs() + dr.abs() + dc.abs() == 1,
+                Neighbourhood3::Cube => (*dz, *dr, *dc) != (0, 0, 0),
+            })
+            .map(move |(dz, dr, dc)| (z as i32 + dz, r as i32 + dr, c as i32 + dc))
+            .filter(move |(z, r, c)| *z >= 0 && *z < layers && *r >= 0 && *r < rows && *c >= 0 && *c < cols)
+            .map(|(z, r, c)| (z as usize, r as usize, c as usize))
+    }
+}
+
+fn voxels(v: &Volume) -> impl Iterator<Item = Voxel> + Clone + '_ {
+    (0..v.len()).flat_map(move |z| (0..v[z].len()).flat_map(move |r| (0..v[z][r].len()).map(move |c| (z, r, c))))
+}
+
+fn is_low_voxel(v: &Volume, n: Neighbourhood3, p: Voxel) -> bool {
+    let h = v[p.0][p.1][p.2];
+    n.neighbours(v, p).all(|(z, r, c)| h < v[z][r][c])
+}
+
+fn volume_risk_level(v: &Volume, n: Neighbourhood3) -> u32 {
+    voxels(v)
+        .filter(|p| is_low_voxel(v, n, *p))
+        .map(|(z, r, c)| v[z][r][c] + 1)
+        .sum()
+}
+
+// the same flood as label_basins, through the voxels
+fn volume_basin_sizes(v: &Volume, n: Neighbourhood3, wall: u32) -> Vec<usize> {
+    let (rows, cols) = (v[0].len(), v[0][0].len());
+    let (labels, lows) = flood_basins(
+        voxels(v),
+        |(z, r, c)| (z * rows + r) * cols + c,
+        |(z, r, c)| v[z][r][c],
+        |p| n.neighbours(v, p),
+        wall,
+    );
+    let mut sizes = vec![0; lows.len()];
+    for b in labels.iter().flatten() {
+        sizes[*b] += 1;
+    }
+    sizes
+}
+
+fn volume_basin_product(v: &Volume, n: Neighbourhood3, wall: u32) -> usize {
+    let mut basin_sizes = volume_basin_sizes(v, n, wall);
     basin_sizes.sort();
-    basin_sizes.iter().rev().take(3).fold(1, |acc, v| acc*v)
+    basin_sizes.iter().rev().take(3).product()
 }
 
 fn main() {
     let opts = Opts::from_args();
     let data = iter_lines(opts.filename);
+    if let Some(n) = opts.voxels {
+        let volume = parse_volume(data);
+        match opts.part_no {
+            1 => println!("{}", volume_risk_level(&volume, n)),
+            2 => println!("{}", volume_basin_product(&volume, n, opts.wall)),
+            _ => panic!("Invalid part"),
+        }
+        return;
+    }
+    if opts.basins || opts.bench {
+        let grid = parse_input(data);
+        let lines = if opts.bench {
+            bench(&grid, opts.neighbourhood, opts.wall, 100)
+        } else {
+            basins_report(&union_basins(&grid, opts.neighbourhood, opts.wall))
+        };
+        for l in lines {
+            println!("{}", l);
+        }
+        return;
+    }
+    if let Some(cost) = opts.path {
+        match corner_path(&parse_input(data), opts.neighbourhood, cost) {
+            Some((cost, path)) => println!("{} ({} steps)", cost, path.len() - 1),
+            None => println!("No path"),
+        }
+        return;
+    }
+    if opts.drainage || opts.dot.is_some() {
+        let grid = parse_input(data);
+        let d = drainage(&grid, opts.neighbourhood);
+        if opts.drainage {
+            for l in drainage_report(&d) {
+                println!("{}", l);
+            }
+        }
+        if let Some(path) = opts.dot {
+            std::fs::write(path, drainage_dot(&grid, &d)).expect("Unable to write graph");
+        }
+        return;
+    }
+    if opts.flood {
+        for l in flood_report(&flood(&parse_input(data), opts.neighbourhood)) {
+            println!("{}", l);
+        }
+        return;
+    }
+    if opts.render || opts.ppm.is_some() {
+        let grid = parse_input(data);
+        let basins = if opts.watershed {
+            watershed(&grid, opts.neighbourhood).basins()
+        } else {
+            label_basins(&grid, opts.neighbourhood, opts.wall)
+        };
+        let colours = colour_cells(&grid, &basins);
+        if opts.render {
+            for l in render_ansi(&grid, &colours) {
+                println!("{}", l);
+            }
+        }
+        if let Some(path) = opts.ppm {
+            std::fs::write(path, render_ppm(&colours, opts.scale)).expect("Unable to write image");
+        }
+        return;
+    }
+    if opts.watershed {
+        let ws = watershed(&parse_input(data), opts.neighbourhood);
+        match opts.part_no {
+            1 => println!("{}", watershed_risk(&ws)),
+            2 => println!("{}", watershed_product(&ws)),
+            _ => panic!("Invalid part"),
+        }
+        return;
+    }
     match opts.part_no {
-        1 => println!("{}", part_1(data)),
-        2 => println!("{}", part_2(data)),
+        1 => println!("{}", part_1(data, opts.neighbourhood)),
+        2 => println!("{}", part_2(data, opts.neighbourhood, opts.wall)),
         _ => panic!("Invalid part"),
     }
 }
@@ -126,6 +986,7 @@ fn main() {
 #[cfg(test)]
 mod test {
     use super::*;
+    use itertools::Itertools;
 
     static TEST_DATA: &str = r"2199943210
     3987894921
@@ -152,21 +1013,402 @@ mod test {
 
     #[test]
     pub fn test_part_1() {
-        assert_eq!(15, part_1(test_data()));
+        assert_eq!(15, part_1(test_data(), Neighbourhood::VonNeumann));
     }
 
     #[test]
     pub fn test_part_2() {
-        assert_eq!(1134, part_2(test_data()));
+        assert_eq!(1134, part_2(test_data(), Neighbourhood::VonNeumann, 9));
+    }
+
+    #[test]
+    pub fn test_neighbourhoods() {
+        let grid = parse_input(test_data());
+        assert_eq!(4, Neighbourhood::VonNeumann.neighbours(&grid, 1, 1).count());
+        assert_eq!(3, Neighbourhood::Moore.neighbours(&grid, 0, 0).count());
+        // even rows lean left, odd rows lean right
+        assert_eq!(vec![(0, 1), (0, 2), (1, 0), (1, 2), (2, 1), (2, 2)],
+            Neighbourhood::Hex.neighbours(&grid, 1, 1).collect::<Vec<_>>());
+        assert_eq!(vec![(1, 0), (1, 1), (2, 0), (2, 2), (3, 0), (3, 1)],
+            Neighbourhood::Hex.neighbours(&grid, 2, 1).collect::<Vec<_>>());
+        assert!("triangle".parse::<Neighbourhood>().is_err());
+    }
+
+    // the 2 and 3 only touch diagonally
+    static DIAGONAL_DATA: &str = r"1949
+    9929
+    9993";
+
+    #[test]
+    pub fn test_moore() {
+        let grid = parse_input(DIAGONAL_DATA.lines().map(String::from));
+        assert_eq!(9, risk_level(&grid, Neighbourhood::VonNeumann));
+        assert_eq!(2, basin_product(&grid, Neighbourhood::VonNeumann, 9));
+        // the 3 drains into the 2, so is no longer a low point
+        assert_eq!(5, risk_level(&grid, Neighbourhood::Moore));
+        assert_eq!(3, basin_product(&grid, Neighbourhood::Moore, 9));
+
+        let grid = parse_input(test_data());
+        assert_eq!(15, risk_level(&grid, Neighbourhood::Moore));
+        assert_eq!(1053, basin_product(&grid, Neighbourhood::Moore, 9));
+    }
+
+    #[test]
+    pub fn test_hex() {
+        let grid = parse_input(DIAGONAL_DATA.lines().map(String::from));
+        assert_eq!(5, risk_level(&grid, Neighbourhood::Hex));
+        assert_eq!(3, basin_product(&grid, Neighbourhood::Hex, 9));
+
+        let grid = parse_input(test_data());
+        assert_eq!(15, risk_level(&grid, Neighbourhood::Hex));
+        assert_eq!(1170, basin_product(&grid, Neighbourhood::Hex, 9));
+    }
+
+    #[test]
+    pub fn test_parse_multi_digit() {
+        let g = heights("10,200,11\n12 200 13");
+        assert_eq!(vec![vec![10, 200, 11], vec![12, 200, 13]], g);
+    }
+
+    #[test]
+    pub fn test_wall() {
+        let g = heights("10,200,11\n12 200 13");
+        assert_eq!(4, basin_product(&g, Neighbourhood::VonNeumann, 100));
+        // the ridge is no longer a wall, so the first basin to reach it takes it
+        let basins = label_basins(&g, Neighbourhood::VonNeumann, 300);
+        assert_eq!(vec![(0, 0), (0, 2)], basins.lows);
+        assert_eq!(vec![vec![Some(0), Some(0), Some(1)], vec![Some(0), Some(0), Some(1)]], basins.labels);
+        assert_eq!(8, basin_product(&g, Neighbourhood::VonNeumann, 300));
+        // only the low points are below the wall
+        assert_eq!(1, basin_product(&g, Neighbourhood::VonNeumann, 12));
+
+        // with no walls at all, every cell is in a basin
+        let basins = label_basins(&parse_input(test_data()), Neighbourhood::VonNeumann, 10);
+        assert_eq!(50, basins.sizes().iter().sum::<usize>());
+        assert!(basins.labels.iter().flatten().all(Option::is_some));
+    }
+
+    #[test]
+    pub fn test_colours() {
+        let grid = parse_input(test_data());
+        let basins = label_basins(&grid, Neighbourhood::VonNeumann, 9);
+        let colours = colour_cells(&grid, &basins);
+        assert_eq!(WALL_COLOUR, colours[0][2]);
+        assert_eq!(LOW_COLOUR, colours[0][1]);
+        // each basin gets a different hue, so the cells next to the low points differ
+        let around = [colours[0][0], colours[0][8], colours[2][1], colours[4][5]];
+        assert_eq!(4, around.iter().unique().count());
+        // higher cells in the same basin are darker
+        let (low, high) = (colours[0][0], colours[1][0]);
+        assert!(high.0 <= low.0 && high.1 <= low.1 && high.2 <= low.2 && high != low);
+
+        let ansi = render_ansi(&grid, &colours);
+        assert_eq!(5, ansi.len());
+        assert!(ansi[0].starts_with("\x1b[30;48;2;"));
+        assert!(ansi[0].contains("m2\x1b") && ansi[0].ends_with("m0\x1b[0m"));
+        assert!(ansi[0].contains(&format!("48;2;{};{};{}m9", WALL_COLOUR.0, WALL_COLOUR.1, WALL_COLOUR.2)));
+
+        let ppm = render_ppm(&colours, 2);
+        let header = b"P6\n20 10\n255\n";
+        assert_eq!(&header[..], &ppm[..header.len()]);
+        assert_eq!(header.len() + 20 * 10 * 3, ppm.len());
+        // second pixel of the first row is still the first cell
+        assert_eq!(ppm[header.len()..header.len() + 3], ppm[header.len() + 3..header.len() + 6]);
+        // an empty image is refused
+        assert!(parse_positive("0").is_err());
+        assert!(Opts::from_iter_safe(["day_09", "2", "--ppm", "out.ppm", "--scale", "0"]).is_err());
+        assert_eq!(1, Opts::from_iter_safe(["day_09", "2", "--ppm", "out.ppm", "--scale", "1"]).unwrap().scale);
+    }
+
+    #[test]
+    pub fn test_render_watershed() {
+        let g = heights("10,200,11\n12 200 13");
+        let basins = watershed(&g, Neighbourhood::VonNeumann).basins();
+        assert_eq!(vec![(0, 0), (0, 2)], basins.lows);
+        let ansi = render_ansi(&g, &colour_cells(&g, &basins));
+        // heights are padded to the widest one
+        assert!(ansi[0].contains("m 10\x1b") && ansi[0].contains("m200\x1b"));
+    }
+
+    #[test]
+    pub fn test_flood() {
+        let f = flood(&heights("1234321"), Neighbourhood::VonNeumann);
+        assert_eq!(Level { height: 1, flooded: 2, basins: 2 }, f.levels[0]);
+        assert_eq!(Level { height: 3, flooded: 6, basins: 2 }, f.levels[2]);
+        assert_eq!(Level { height: 4, flooded: 7, basins: 1 }, f.levels[3]);
+        // equally deep, so the first one survives
+        assert_eq!(vec![Merge { level: 4, survivor: ((0, 0), 1), absorbed: ((0, 6), 1) }], f.merges);
+        assert_eq!(vec![(1, Some(4)), (1, None)], f.persistence());
+
+        // flat minima flood as one basin
+        let f = flood(&heights("3333\n3113\n3333"), Neighbourhood::VonNeumann);
+        assert_eq!(vec![Level { height: 1, flooded: 2, basins: 1 }, Level { height: 3, flooded: 12, basins: 1 }], f.levels);
+        assert!(f.merges.is_empty());
+        assert_eq!(vec![(1, None)], f.persistence());
+    }
+
+    #[test]
+    pub fn test_flood_test_data() {
+        let f = flood(&parse_input(test_data()), Neighbourhood::VonNeumann);
+        assert_eq!(Level { height: 9, flooded: 50, basins: 1 }, *f.levels.last().unwrap());
+        // the basins walled off by 9s only join when the 9s go under
+        assert!(f.levels.iter().filter(|l| l.height >= 5 && l.height < 9).all(|l| l.basins == 4));
+        assert_eq!(3, f.merges.iter().filter(|m| m.level == 9).count());
+        let mut persistence = f.persistence();
+        persistence.sort();
+        assert_eq!(4, persistence.len());
+        assert_eq!((0, None), persistence[0]);
+        let report = flood_report(&f);
+        assert_eq!("level 0: 1 cells flooded, 1 basins", report[0]);
+        assert_eq!("  0 -> never", report.last().unwrap());
+    }
+
+    #[test]
+    pub fn test_drainage() {
+        // the peak drains left, the first of its equally low neighbours
+        let g = heights("1234321");
+        let d = drainage(&g, Neighbourhood::VonNeumann);
+        assert_eq!(Some((0, 2)), d.downstream[0][3]);
+        assert_eq!(vec![((0, 0), 1), ((0, 6), 1)], d.sinks);
+        assert_eq!(vec![vec![0, 0, 0, 0, 1, 1, 1]], d.basin);
+        assert_eq!(vec![4, 3], d.catchments());
+        assert_eq!(vec![((0, 3), 3), ((0, 4), 2)], d.longest_flows());
+        assert_eq!(vec![((0, 1), 4)], d.passes.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    pub fn test_drainage_plateaus() {
+        // the shelf drains across to its edges, so only the two minima are sinks
+        let d = drainage(&heights("5552\n5555\n1555"), Neighbourhood::VonNeumann);
+        assert_eq!(vec![((0, 3), 2), ((2, 0), 1)], d.sinks);
+        assert_eq!(vec![vec![1, 0, 0, 0], vec![1, 1, 0, 0], vec![1, 1, 1, 0]], d.basin);
+        assert_eq!(Some((0, 2)), d.downstream[0][1]);
+        assert_eq!(vec![6, 6], d.catchments());
+        assert_eq!(vec![((0, 1), 2), ((0, 0), 2)], d.longest_flows());
+        assert_eq!(vec![((0, 1), 5)], d.passes.into_iter().collect::<Vec<_>>());
+
+        // a flat minimum drains to its first cell
+        let d = drainage(&heights("3333\n3113\n3333"), Neighbourhood::VonNeumann);
+        assert_eq!(vec![((1, 1), 1)], d.sinks);
+        assert_eq!(Some((1, 1)), d.downstream[1][2]);
+        assert_eq!(None, d.downstream[1][1]);
+        assert_eq!(vec![12], d.catchments());
+        assert!(d.passes.is_empty());
+
+        // ridges of 9s drain somewhere rather than being basins of their own
+        let g = parse_input(real_data());
+        let d = drainage(&g, Neighbourhood::VonNeumann);
+        assert_eq!(union_basins(&g, Neighbourhood::VonNeumann, 9).len(), d.sinks.len());
+    }
+
+    #[test]
+    pub fn test_drainage_test_data() {
+        let g = parse_input(test_data());
+        let d = drainage(&g, Neighbourhood::VonNeumann);
+        assert_eq!(50, d.catchments().iter().sum::<usize>());
+        // the low points are the sinks, with the 9s shared out between them
+        assert_eq!(4, d.sinks.len());
+        for (r, c) in label_basins(&g, Neighbourhood::VonNeumann, 9).lows {
+            assert!(d.sinks.iter().any(|(p, _)| *p == (r, c)));
+        }
+        let report = drainage_report(&d);
+        assert_eq!(d.sinks.len() + d.passes.len(), report.len());
+        assert!(report[0].starts_with("basin 0 at (0,1) height 1:"));
+
+        let dot = drainage_dot(&g, &d);
+        assert!(dot.starts_with("digraph drainage {\n  subgraph cluster_0 {"));
+        assert!(dot.contains("c0_1 [label=\"1\", shape=doublecircle];"));
+        assert!(dot.contains("c0_0 -> c0_1;"));
+        // one edge out of every cell but the sinks, and one per pass
+        assert_eq!(50 - 4 + d.passes.len(), dot.matches(" -> ").count());
+    }
+
+    #[test]
+    pub fn test_path_cost() {
+        assert_eq!(Ok(PathCost::Entered), "entered".parse());
+        assert_eq!(Ok(PathCost::Climb(1)), "climb".parse());
+        assert_eq!(Ok(PathCost::Climb(3)), "climb:3".parse());
+        assert!("climb:x".parse::<PathCost>().is_err());
+        assert!("fly".parse::<PathCost>().is_err());
+    }
+
+    #[test]
+    pub fn test_climb() {
+        let g = heights("1234321");
+        let steps = grid::orthogonal(1, 7);
+        let (cost, path) = grid::dijkstra((0, 0), (0, 6), &steps, grid::limited_climb(&g, 1)).unwrap();
+        assert_eq!(6, cost);
+        assert_eq!((0..7).map(|c| (0, c)).collect::<Vec<_>>(), path);
+        // going back down is free of the limit
+        assert_eq!(Some(3), grid::dijkstra((0, 3), (0, 0), &steps, grid::limited_climb(&g, 0)).map(|(c, _)| c));
+
+        // the step up to 3 is too steep unless there's a way round
+        let g = heights("1334");
+        assert_eq!(None, grid::dijkstra((0, 0), (0, 3), grid::orthogonal(1, 4), grid::limited_climb(&g, 1)));
+        assert_eq!(Some(3), grid::dijkstra((0, 0), (0, 3), grid::orthogonal(1, 4), grid::limited_climb(&g, 2)).map(|(c, _)| c));
+        let g = heights("1334\n1233");
+        assert_eq!(Some(4), corner_path(&g, Neighbourhood::VonNeumann, PathCost::Climb(1)).map(|(c, _)| c));
+    }
+
+    #[test]
+    pub fn test_paths_test_data() {
+        let g = parse_input(test_data());
+        let goal = (4, 9);
+        let (cost, path) = grid::dijkstra((0, 0), goal, grid::orthogonal(5, 10), grid::entered_height(&g)).unwrap();
+        assert_eq!(Some((cost, path.clone())), corner_path(&g, Neighbourhood::VonNeumann, PathCost::Entered));
+        assert_eq!(cost, path[1..].iter().map(|(r, c)| g[*r][*c] as u64).sum::<u64>());
+        // along the top row and down the right hand side is no better
+        let edge: u64 = g[0][1..].iter().chain(g[1..].iter().map(|r| &r[9])).map(|h| *h as u64).sum();
+        assert!(cost <= edge);
+
+        // the 9s block climbing, but with no limit every path is the same length
+        let (cost, path) = corner_path(&g, Neighbourhood::VonNeumann, PathCost::Climb(9)).unwrap();
+        assert_eq!(13, cost);
+        assert_eq!(14, path.len());
+        for w in path.windows(2) {
+            assert_eq!(1, grid::manhattan(w[0])(w[1]));
+        }
+        // the top left basin is walled in by 9s
+        assert_eq!(None, corner_path(&g, Neighbourhood::VonNeumann, PathCost::Climb(1)));
+        assert_eq!(Some(9), corner_path(&g, Neighbourhood::Moore, PathCost::Climb(9)).map(|(c, _)| c));
+    }
+
+    #[test]
+    pub fn test_paths_real() {
+        // guiding the search must not change the answer
+        let g = parse_input(real_data());
+        let goal = (g.len() - 1, g[0].len() - 1);
+        let steps = grid::orthogonal(g.len(), g[0].len());
+        let (cost, _) = grid::dijkstra((0, 0), goal, &steps, grid::entered_height(&g)).unwrap();
+        assert_eq!(Some(cost), corner_path(&g, Neighbourhood::VonNeumann, PathCost::Entered).map(|(c, _)| c));
+        let cost = grid::dijkstra((0, 0), goal, &steps, grid::limited_climb(&g, 3)).map(|(c, _)| c);
+        assert_eq!(cost, corner_path(&g, Neighbourhood::VonNeumann, PathCost::Climb(3)).map(|(c, _)| c));
+    }
+
+    #[test]
+    pub fn test_union_basins() {
+        let g = parse_input(test_data());
+        let basins = union_basins(&g, Neighbourhood::VonNeumann, 9);
+        assert_eq!(vec![
+            Basin { lows: vec![(0, 1)], size: 3 },
+            Basin { lows: vec![(0, 9)], size: 9 },
+            Basin { lows: vec![(2, 2)], size: 14 },
+            Basin { lows: vec![(4, 6)], size: 9 },
+        ], basins);
+        assert_eq!("3 cells, low points (0,1)", basins_report(&basins)[0]);
+
+        // with nothing between them, two low points share a basin
+        let basins = union_basins(&heights("121"), Neighbourhood::VonNeumann, 9);
+        assert_eq!(vec![Basin { lows: vec![(0, 0), (0, 2)], size: 3 }], basins);
+
+        // the 3 drains into the 2, so is no longer a low point
+        let g = parse_input(DIAGONAL_DATA.lines().map(String::from));
+        let basins = union_basins(&g, Neighbourhood::Moore, 9);
+        assert_eq!(vec![Basin { lows: vec![(0, 0)], size: 1 }, Basin { lows: vec![(1, 2)], size: 3 }], basins);
+    }
+
+    #[test]
+    pub fn test_union_basins_real() {
+        // each walled region has one low point, so both ways agree
+        let g = parse_input(real_data());
+        let basins = union_basins(&g, Neighbourhood::VonNeumann, 9);
+        assert!(basins.iter().all(|b| b.lows.len() == 1));
+        let mut sizes: Vec<_> = basins.iter().map(|b| b.size).collect();
+        let mut bfs_sizes = label_basins(&g, Neighbourhood::VonNeumann, 9).sizes();
+        sizes.sort();
+        bfs_sizes.sort();
+        assert_eq!(bfs_sizes, sizes);
+    }
+
+    fn volume(s: &str) -> Volume {
+        parse_volume(s.lines().map(String::from))
+    }
+
+    #[test]
+    pub fn test_parse_volume() {
+        let v = volume("12\n34\n\n56\n78\n");
+        assert_eq!(vec![vec![vec![1, 2], vec![3, 4]], vec![vec![5, 6], vec![7, 8]]], v);
+        assert_eq!(3, Neighbourhood3::Faces.neighbours(&v, (0, 0, 0)).count());
+        assert_eq!(7, Neighbourhood3::Cube.neighbours(&v, (1, 1, 1)).count());
+        let v = volume("999\n999\n999\n\n999\n999\n999\n\n999\n999\n999");
+        assert_eq!(6, Neighbourhood3::Faces.neighbours(&v, (1, 1, 1)).count());
+        assert_eq!(26, Neighbourhood3::Cube.neighbours(&v, (1, 1, 1)).count());
+    }
+
+    #[test]
+    pub fn test_volume_single_layer() {
+        // one layer is the 2-D case
+        let v = parse_volume(test_data());
+        assert_eq!(15, volume_risk_level(&v, Neighbourhood3::Faces));
+        assert_eq!(1134, volume_basin_product(&v, Neighbourhood3::Faces, 9));
+        assert_eq!(15, volume_risk_level(&v, Neighbourhood3::Cube));
+        assert_eq!(1053, volume_basin_product(&v, Neighbourhood3::Cube, 9));
+
+        // a layer of 9s on top changes nothing
+        let v = parse_volume(test_data().chain(std::iter::once(String::new())).chain(test_data().map(|l| l.replace(|c: char| c.is_ascii_digit(), "9"))));
+        assert_eq!(2, v.len());
+        assert_eq!(15, volume_risk_level(&v, Neighbourhood3::Faces));
+        assert_eq!(1134, volume_basin_product(&v, Neighbourhood3::Faces, 9));
+
+        // but the same layer twice has no strict low points
+        let v = parse_volume(test_data().chain(std::iter::once(String::new())).chain(test_data()));
+        assert_eq!(0, volume_risk_level(&v, Neighbourhood3::Faces));
+    }
+
+    #[test]
+    pub fn test_volume() {
+        // a hollow in the middle of a cube, with a second one touching it only at a corner
+        let v = volume("999\n999\n999\n\n999\n919\n999\n\n999\n999\n993");
+        assert_eq!(2 + 4, volume_risk_level(&v, Neighbourhood3::Faces));
+        assert_eq!(1, volume_basin_product(&v, Neighbourhood3::Faces, 9));
+        // the 3 drains into the 1
+        assert_eq!(2, volume_risk_level(&v, Neighbourhood3::Cube));
+        assert_eq!(vec![2], volume_basin_sizes(&v, Neighbourhood3::Cube, 9));
+    }
+
+    fn heights(s: &str) -> Grid {
+        parse_input(s.lines().map(String::from))
+    }
+
+    #[test]
+    pub fn test_watershed_plateau() {
+        // the two 1s are a minimum together, but neither is lower than the other
+        let g = heights("3333\n3113\n3333");
+        assert_eq!(0, risk_level(&g, Neighbourhood::VonNeumann));
+        let ws = watershed(&g, Neighbourhood::VonNeumann);
+        assert_eq!(vec![((1, 1), 1)], ws.minima);
+        assert_eq!(2, watershed_risk(&ws));
+        assert_eq!(vec![12], ws.sizes());
+
+        // a shelf drains across to its edge before heading down
+        let g = heights("5552\n5555\n1555");
+        let ws = watershed(&g, Neighbourhood::VonNeumann);
+        assert_eq!(vec![((0, 3), 2), ((2, 0), 1)], ws.minima);
+        assert_eq!(vec![vec![1, 0, 0, 0], vec![1, 1, 0, 0], vec![1, 1, 1, 0]], ws.labels);
+    }
+
+    #[test]
+    pub fn test_watershed_no_walls() {
+        // the peak falls to the left first
+        let ws = watershed(&heights("1234321"), Neighbourhood::VonNeumann);
+        assert_eq!(vec![((0, 0), 1), ((0, 6), 1)], ws.minima);
+        assert_eq!(vec![vec![0, 0, 0, 0, 1, 1, 1]], ws.labels);
+        assert_eq!(12, watershed_product(&ws));
+
+        // every cell ends up in some basin, 9s included
+        let ws = watershed(&parse_input(test_data()), Neighbourhood::VonNeumann);
+        assert_eq!(4, ws.minima.len());
+        assert_eq!(15, watershed_risk(&ws));
+        assert_eq!(50, ws.sizes().iter().sum::<usize>());
     }
 
     #[test]
     pub fn test_part_1_real() {
-        assert_eq!(554, part_1(real_data()));
+        assert_eq!(554, part_1(real_data(), Neighbourhood::VonNeumann));
     }
 
     #[test]
     pub fn test_part_2_real() {
-        assert_eq!(1017792, part_2(real_data()));
+        assert_eq!(1017792, part_2(real_data(), Neighbourhood::VonNeumann, 9));
     }
 }
@@ -0,0 +1,183 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::hash::Hash;
+
+// (row, col) of a grid cell
+pub type Pos = (usize, usize);
+
+// The cheapest path from start to goal and its cost, or None if the goal can't be reached.
+// cost(from, to) gives the price of a step, or None if the step isn't allowed.
+// heuristic(p) guides the search towards the goal, and must never overestimate
+// the remaining cost from p or the path found may not be the cheapest.
+pub fn astar<T, I>(
+    start: T,
+    goal: T,
+    mut neighbours: impl FnMut(T) -> I,
+    mut cost: impl FnMut(T, T) -> Option<u64>,
+    mut heuristic: impl FnMut(T) -> u64,
+) -> Option<(u64, Vec<T>)>
+where
+    T: Copy + Eq + Hash + Ord,
+    I: IntoIterator<Item = T>,
+{
+    let mut best = HashMap::new();
+    let mut prev = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    best.insert(start, 0);
+    queue.push(Reverse((heuristic(start), 0, start)));
+
+    while let Some(Reverse((_, d, p))) = queue.pop() {
+        if p == goal {
+            // walk back to the start
+            let mut path = vec![goal];
+            let mut p = goal;
+            while let Some(q) = prev.get(&p) {
+                path.push(*q);
+                p = *q;
+            }
+            path.reverse();
+            return Some((d, path));
+        }
+        // already reached more cheaply
+        if d > best[&p] {
+            continue;
+        }
+        for q in neighbours(p) {
+            if let Some(c) = cost(p, q) {
+                let d = d + c;
+                if best.get(&q).is_none_or(|b| d < *b) {
+                    best.insert(q, d);
+                    prev.insert(q, p);
+                    queue.push(Reverse((d + heuristic(q), d, q)));
+                }
+            }
+        }
+    }
+    None
+}
+
+// A* with no guidance
+pub fn dijkstra<T, I>(
+    start: T,
+    goal: T,
+    neighbours: impl FnMut(T) -> I,
+    cost: impl FnMut(T, T) -> Option<u64>,
+) -> Option<(u64, Vec<T>)>
+where
+    T: Copy + Eq + Hash + Ord,
+    I: IntoIterator<Item = T>,
+{
+    astar(start, goal, neighbours, cost, |_| 0)
+}
+
+// the 4 orthogonal cells inside a rows x cols grid
+pub fn orthogonal(rows: usize, cols: usize) -> impl Fn(Pos) -> Vec<Pos> {
+    move |(r, c)| {
+        let mut ns = Vec::with_capacity(4);
+        if r > 0 {
+            ns.push((r - 1, c));
+        }
+        if r + 1 < rows {
+            ns.push((r + 1, c));
+        }
+        if c > 0 {
+            ns.push((r, c - 1));
+        }
+        if c + 1 < cols {
+            ns.push((r, c + 1));
+        }
+        ns
+    }
+}
+
+// each step costs the height of the cell it enters
+pub fn entered_height(g: &[Vec<u32>]) -> impl Fn(Pos, Pos) -> Option<u64> + '_ {
+    move |_, (r, c)| Some(g[r][c] as u64)
+}
+
+// each step costs 1, and may rise at most max_climb (but drop any distance)
+pub fn limited_climb(g: &[Vec<u32>], max_climb: u32) -> impl Fn(Pos, Pos) -> Option<u64> + '_ {
+    move |(r, c), (nr, nc)| {
+        if g[nr][nc] <= g[r][c].saturating_add(max_climb) {
+            Some(1)
+        } else {
+            None
+        }
+    }
+}
+
+// orthogonal steps to the goal, a lower bound on the remaining steps
+pub fn manhattan(goal: Pos) -> impl Fn(Pos) -> u64 {
+    move |(r, c)| (r.abs_diff(goal.0) + c.abs_diff(goal.1)) as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn heights(s: &str) -> Vec<Vec<u32>> {
+        s.lines().map(|l| l.chars().map(|c| c.to_digit(10).unwrap()).collect()).collect()
+    }
+
+    #[test]
+    pub fn test_orthogonal() {
+        let steps = orthogonal(3, 4);
+        assert_eq!(vec![(1, 0), (0, 1)], steps((0, 0)));
+        assert_eq!(vec![(0, 1), (2, 1), (1, 0), (1, 2)], steps((1, 1)));
+        assert_eq!(vec![(1, 3), (2, 2)], steps((2, 3)));
+        assert!(orthogonal(1, 1)((0, 0)).is_empty());
+    }
+
+    #[test]
+    pub fn test_costs() {
+        let g = heights("159\n234");
+        assert_eq!(Some(9), entered_height(&g)((0, 1), (0, 2)));
+        assert_eq!(Some(1), limited_climb(&g, 4)((0, 0), (0, 1)));
+        assert_eq!(None, limited_climb(&g, 3)((0, 0), (0, 1)));
+        // any drop is allowed
+        assert_eq!(Some(1), limited_climb(&g, 0)((0, 2), (1, 2)));
+        // any climb at all, without overflowing
+        let g = vec![vec![u32::MAX, 0, u32::MAX]];
+        assert_eq!(Some(1), limited_climb(&g, u32::MAX)((0, 1), (0, 2)));
+        assert_eq!(Some(1), limited_climb(&g, u32::MAX)((0, 0), (0, 1)));
+        assert_eq!(None, limited_climb(&g, u32::MAX - 1)((0, 1), (0, 2)));
+        assert_eq!(0, manhattan((1, 2))((1, 2)));
+        assert_eq!(3, manhattan((1, 2))((0, 0)));
+        assert_eq!(3, manhattan((0, 0))((1, 2)));
+    }
+
+    #[test]
+    pub fn test_dijkstra() {
+        // cheaper to go round the wall of 9s than through it
+        let g = heights("191\n191\n111");
+        let (cost, path) = dijkstra((0, 0), (0, 2), orthogonal(3, 3), entered_height(&g)).unwrap();
+        assert_eq!(6, cost);
+        assert_eq!(vec![(0, 0), (1, 0), (2, 0), (2, 1), (2, 2), (1, 2), (0, 2)], path);
+        assert_eq!(Some((0, vec![(1, 1)])), dijkstra((1, 1), (1, 1), orthogonal(3, 3), entered_height(&g)));
+        // the 9s can't be climbed
+        assert_eq!(None, dijkstra((0, 0), (1, 1), orthogonal(3, 3), limited_climb(&g, 7)));
+
+        // not only grids: doubling or adding one, from 1 to 10
+        let steps = |n: u32| vec![n + 1, n * 2].into_iter().filter(|m| *m <= 20);
+        assert_eq!(Some((4, vec![1, 2, 4, 5, 10])), dijkstra(1, 10, steps, |_, _| Some(1)));
+        assert_eq!(None, dijkstra(1, 0, steps, |_, _| Some(1)));
+    }
+
+    #[test]
+    pub fn test_astar() {
+        let g = heights("191\n191\n111");
+        let goal = (0, 2);
+        let guided = astar((0, 0), goal, orthogonal(3, 3), entered_height(&g), manhattan(goal));
+        assert_eq!(dijkstra((0, 0), goal, orthogonal(3, 3), entered_height(&g)), guided);
+
+        // a heuristic that never overestimates finds the same cost as no guidance
+        let g = heights("1163751742\n1381373672\n2136511328\n3694931569\n7463417111\n1319128137\n1359912421\n3125421639\n1293138521\n2311944581");
+        let goal = (9, 9);
+        let (cost, path) = astar((0, 0), goal, orthogonal(10, 10), entered_height(&g), manhattan(goal)).unwrap();
+        assert_eq!(40, cost);
+        assert_eq!(Some(40), dijkstra((0, 0), goal, orthogonal(10, 10), entered_height(&g)).map(|(c, _)| c));
+        assert_eq!((0, 0), path[0]);
+        assert_eq!(goal, *path.last().unwrap());
+        assert_eq!(cost, path[1..].iter().map(|(r, c)| g[*r][*c] as u64).sum());
+    }
+}